@@ -0,0 +1,13 @@
+[
+  (function_definition)
+  (struct_specifier)
+  (union_specifier)
+  (enum_specifier)
+  (for_statement)
+  (while_statement)
+  (do_statement)
+  (if_statement)
+  (else_clause)
+  (switch_statement)
+  (case_statement)
+] @context
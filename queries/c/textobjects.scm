@@ -0,0 +1,78 @@
+; Functions
+(function_definition) @function.outer
+
+(function_definition
+  (compound_statement) @function.inner
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+; Classes
+[
+  (struct_specifier
+    (field_declaration_list))
+  (union_specifier
+    (field_declaration_list))
+  (enum_specifier
+    (enumerator_list))
+] @class.outer
+
+([
+  (struct_specifier
+    (field_declaration_list) @class.inner)
+  (union_specifier
+    (field_declaration_list) @class.inner)
+  (enum_specifier
+    (enumerator_list) @class.inner)
+]
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+; Parameters
+[
+  (parameter_list
+    (_) @parameter.inner)
+  (argument_list
+    (_) @parameter.inner)
+]
+
+[
+  (parameter_list
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (argument_list
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+]
+
+[
+  (parameter_list
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (argument_list
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+]
+
+; Blocks
+(compound_statement) @block.outer
+
+((compound_statement) @block.inner
+  (#offset! @block.inner 0 1 0 -1)
+  (#trim! @block.inner 1 1 1 1))
+
+; Calls
+(call_expression) @call.outer
+
+(call_expression
+  (argument_list) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+(comment) @comment.outer
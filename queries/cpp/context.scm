@@ -0,0 +1,11 @@
+; inherits: c
+
+[
+  (namespace_definition)
+  (class_specifier)
+  (template_declaration)
+  (lambda_expression)
+  (for_range_loop)
+  (try_statement)
+  (catch_clause)
+] @context
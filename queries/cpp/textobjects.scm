@@ -0,0 +1,22 @@
+; inherits: c
+
+; Functions
+(lambda_expression) @function.outer
+
+(lambda_expression
+  (compound_statement) @function.inner
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+; Classes
+(class_specifier
+  (field_declaration_list)) @class.outer
+
+(class_specifier
+  (field_declaration_list) @class.inner
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+; Parameters
+(template_parameter_list
+  (_) @parameter.inner @parameter.outer)
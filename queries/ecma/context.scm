@@ -0,0 +1,18 @@
+[
+  (class_declaration)
+  (function_declaration)
+  (generator_function_declaration)
+  (method_definition)
+  (arrow_function)
+  (function_expression)
+  (for_statement)
+  (for_in_statement)
+  (while_statement)
+  (do_statement)
+  (if_statement)
+  (else_clause)
+  (switch_statement)
+  (switch_case)
+  (try_statement)
+  (catch_clause)
+] @context
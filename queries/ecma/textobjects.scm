@@ -0,0 +1,81 @@
+; Functions
+[
+  (function_declaration)
+  (function_expression)
+  (generator_function_declaration)
+  (arrow_function)
+  (method_definition)
+] @function.outer
+
+([
+  (function_declaration
+    (statement_block) @function.inner)
+  (function_expression
+    (statement_block) @function.inner)
+  (generator_function_declaration
+    (statement_block) @function.inner)
+  (arrow_function
+    (statement_block) @function.inner)
+  (method_definition
+    (statement_block) @function.inner)
+]
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+; Classes
+(class_declaration) @class.outer
+
+((class_declaration
+  (class_body) @class.inner)
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+; Parameters
+[
+  (formal_parameters
+    (_) @parameter.inner)
+  (arguments
+    (_) @parameter.inner)
+]
+
+[
+  (formal_parameters
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (arguments
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+]
+
+[
+  (formal_parameters
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (arguments
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+]
+
+; Blocks
+(statement_block) @block.outer
+
+((statement_block) @block.inner
+  (#offset! @block.inner 0 1 0 -1)
+  (#trim! @block.inner 1 1 1 1))
+
+; Calls
+(call_expression) @call.outer
+
+(call_expression
+  (arguments) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+(comment) @comment.outer
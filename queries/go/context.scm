@@ -0,0 +1,15 @@
+[
+  (function_declaration)
+  (method_declaration)
+  (func_literal)
+  (type_declaration)
+  (for_statement)
+  (if_statement)
+  (expression_switch_statement)
+  (type_switch_statement)
+  (select_statement)
+  (expression_case)
+  (type_case)
+  (communication_case)
+  (default_case)
+] @context
@@ -0,0 +1,80 @@
+; Functions
+[
+  (function_declaration)
+  (method_declaration)
+  (func_literal)
+] @function.outer
+
+([
+  (function_declaration
+    (block) @function.inner)
+  (method_declaration
+    (block) @function.inner)
+  (func_literal
+    (block) @function.inner)
+]
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+; Classes
+(type_declaration
+  (type_spec
+    [
+      (struct_type)
+      (interface_type)
+    ])) @class.outer
+
+((struct_type
+  (field_declaration_list) @class.inner)
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+; Parameters
+[
+  (parameter_list
+    (_) @parameter.inner)
+  (argument_list
+    (_) @parameter.inner)
+]
+
+[
+  (parameter_list
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (argument_list
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+]
+
+[
+  (parameter_list
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (argument_list
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+]
+
+; Blocks
+(block) @block.outer
+
+((block) @block.inner
+  (#offset! @block.inner 0 1 0 -1)
+  (#trim! @block.inner 1 1 1 1))
+
+; Calls
+(call_expression) @call.outer
+
+(call_expression
+  (argument_list) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+(comment) @comment.outer
@@ -0,0 +1,17 @@
+[
+  (class_declaration)
+  (interface_declaration)
+  (enum_declaration)
+  (record_declaration)
+  (method_declaration)
+  (constructor_declaration)
+  (lambda_expression)
+  (for_statement)
+  (enhanced_for_statement)
+  (while_statement)
+  (do_statement)
+  (if_statement)
+  (switch_expression)
+  (try_statement)
+  (catch_clause)
+] @context
@@ -0,0 +1,86 @@
+; Functions
+[
+  (method_declaration)
+  (constructor_declaration)
+  (lambda_expression)
+] @function.outer
+
+([
+  (method_declaration
+    (block) @function.inner)
+  (constructor_declaration
+    (constructor_body) @function.inner)
+]
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+; Classes
+[
+  (class_declaration)
+  (interface_declaration)
+  (enum_declaration)
+] @class.outer
+
+([
+  (class_declaration
+    (class_body) @class.inner)
+  (interface_declaration
+    (interface_body) @class.inner)
+  (enum_declaration
+    (enum_body) @class.inner)
+]
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+; Parameters
+[
+  (formal_parameters
+    (_) @parameter.inner)
+  (argument_list
+    (_) @parameter.inner)
+]
+
+[
+  (formal_parameters
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (argument_list
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+]
+
+[
+  (formal_parameters
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (argument_list
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+]
+
+; Blocks
+(block) @block.outer
+
+((block) @block.inner
+  (#offset! @block.inner 0 1 0 -1)
+  (#trim! @block.inner 1 1 1 1))
+
+; Calls
+(method_invocation) @call.outer
+
+(method_invocation
+  (argument_list) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+[
+  (line_comment)
+  (block_comment)
+] @comment.outer
@@ -0,0 +1,11 @@
+[
+  (function_declaration)
+  (function_definition)
+  (for_statement)
+  (while_statement)
+  (repeat_statement)
+  (if_statement)
+  (elseif_statement)
+  (else_statement)
+  (do_statement)
+] @context
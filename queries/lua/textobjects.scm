@@ -0,0 +1,48 @@
+; Functions
+[
+  (function_declaration)
+  (function_definition)
+] @function.outer
+
+; Parameters
+[
+  (parameters
+    (_) @parameter.inner)
+  (arguments
+    (_) @parameter.inner)
+]
+
+[
+  (parameters
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (arguments
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+]
+
+[
+  (parameters
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (arguments
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+]
+
+; Calls
+(function_call) @call.outer
+
+(function_call
+  (arguments) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+(comment) @comment.outer
@@ -0,0 +1,16 @@
+[
+  (class_definition)
+  (function_definition)
+  (decorated_definition)
+  (for_statement)
+  (while_statement)
+  (if_statement)
+  (elif_clause)
+  (else_clause)
+  (try_statement)
+  (except_clause)
+  (finally_clause)
+  (with_statement)
+  (match_statement)
+  (case_clause)
+] @context
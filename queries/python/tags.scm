@@ -0,0 +1,37 @@
+(class_definition
+  name: (identifier) @name) @definition.class
+
+(class_definition
+  body: (block
+    [
+      (function_definition
+        name: (identifier) @name) @definition.method
+      (decorated_definition
+        definition: (function_definition
+          name: (identifier) @name) @definition.method)
+    ]))
+
+(module
+  [
+    (function_definition
+      name: (identifier) @name) @definition.function
+    (decorated_definition
+      definition: (function_definition
+        name: (identifier) @name) @definition.function)
+  ])
+
+((block
+  [
+    (function_definition
+      name: (identifier) @name) @definition.function
+    (decorated_definition
+      definition: (function_definition
+        name: (identifier) @name) @definition.function)
+  ]) @_body
+  (#not-has-parent? @_body class_definition))
+
+(module
+  (expression_statement
+    (assignment
+      left: (identifier) @name) @definition.constant)
+  (#match? @name "^[A-Z][A-Z0-9_]*$"))
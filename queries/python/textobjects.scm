@@ -0,0 +1,77 @@
+; Functions
+[
+  (function_definition)
+  (decorated_definition
+    (function_definition))
+  (lambda)
+] @function.outer
+
+(function_definition
+  (block) @function.inner)
+
+; Classes
+[
+  (class_definition)
+  (decorated_definition
+    (class_definition))
+] @class.outer
+
+(class_definition
+  (block) @class.inner)
+
+; Parameters
+[
+  (parameters
+    (_) @parameter.inner)
+  (lambda_parameters
+    (_) @parameter.inner)
+  (argument_list
+    (_) @parameter.inner)
+]
+
+[
+  (parameters
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (lambda_parameters
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+  (argument_list
+    "," @parameter.outer
+    .
+    (_) @parameter.outer)
+]
+
+[
+  (parameters
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (lambda_parameters
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+  (argument_list
+    .
+    (_) @parameter.outer
+    .
+    ","? @parameter.outer)
+]
+
+; Blocks
+(block) @block.inner @block.outer
+
+; Calls
+(call) @call.outer
+
+(call
+  (argument_list) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+(comment) @comment.outer
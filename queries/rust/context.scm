@@ -0,0 +1,20 @@
+[
+  (mod_item)
+  (foreign_mod_item)
+  (function_item)
+  (function_signature_item)
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (trait_item)
+  (impl_item)
+  (macro_definition)
+  (closure_expression)
+  (loop_expression)
+  (for_expression)
+  (while_expression)
+  (if_expression)
+  (else_clause)
+  (match_expression)
+  (match_arm)
+] @context
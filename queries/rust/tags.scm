@@ -0,0 +1,41 @@
+(mod_item
+  name: (identifier) @name) @definition.module
+
+(struct_item
+  name: (type_identifier) @name) @definition.struct
+
+(enum_item
+  name: (type_identifier) @name) @definition.enum
+
+(union_item
+  name: (type_identifier) @name) @definition.struct
+
+(type_item
+  name: (type_identifier) @name) @definition.type
+
+(trait_item
+  name: (type_identifier) @name) @definition.interface
+
+(impl_item
+  type: (_) @name) @definition.impl
+
+(declaration_list
+  (function_item
+    name: (identifier) @name) @definition.method)
+
+((function_item
+  name: (identifier) @name) @definition.function
+  (#not-has-parent? @definition.function declaration_list))
+
+(declaration_list
+  (function_signature_item
+    name: (identifier) @name) @definition.method)
+
+(const_item
+  name: (identifier) @name) @definition.constant
+
+(static_item
+  name: (identifier) @name) @definition.constant
+
+(macro_definition
+  name: (identifier) @name) @definition.macro
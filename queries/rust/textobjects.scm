@@ -0,0 +1,119 @@
+; Functions
+(function_item) @function.outer
+
+(function_item
+  (block) @function.inner
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+(closure_expression) @function.outer
+
+(closure_expression
+  (block) @function.inner
+  (#offset! @function.inner 0 1 0 -1)
+  (#trim! @function.inner 1 1 1 1))
+
+; Classes
+[
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (impl_item)
+  (trait_item)
+] @class.outer
+
+(struct_item
+  (field_declaration_list) @class.inner
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+(union_item
+  (field_declaration_list) @class.inner
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+(enum_item
+  (enum_variant_list) @class.inner
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+(impl_item
+  (declaration_list) @class.inner
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+(trait_item
+  (declaration_list) @class.inner
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+; Parameters
+(parameters
+  [
+    (parameter)
+    (self_parameter)
+  ] @parameter.inner)
+
+(parameters
+  "," @parameter.outer
+  .
+  [
+    (parameter)
+    (self_parameter)
+  ] @parameter.outer)
+
+(parameters
+  .
+  [
+    (parameter)
+    (self_parameter)
+  ] @parameter.outer
+  .
+  ","? @parameter.outer)
+
+(closure_parameters
+  (_) @parameter.inner @parameter.outer)
+
+(arguments
+  (_) @parameter.inner)
+
+(arguments
+  "," @parameter.outer
+  .
+  (_) @parameter.outer)
+
+(arguments
+  .
+  (_) @parameter.outer
+  .
+  ","? @parameter.outer)
+
+(type_parameters
+  (_) @parameter.inner @parameter.outer)
+
+(type_arguments
+  (_) @parameter.inner @parameter.outer)
+
+; Blocks
+(block) @block.outer
+
+((block) @block.inner
+  (#offset! @block.inner 0 1 0 -1)
+  (#trim! @block.inner 1 1 1 1))
+
+; Calls
+[
+  (call_expression)
+  (macro_invocation)
+] @call.outer
+
+(call_expression
+  (arguments) @call.inner
+  (#offset! @call.inner 0 1 0 -1)
+  (#trim! @call.inner 1 1 1 1))
+
+; Comments
+[
+  (line_comment)
+  (block_comment)
+] @comment.outer
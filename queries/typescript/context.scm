@@ -0,0 +1,8 @@
+; inherits: ecma
+
+[
+  (abstract_class_declaration)
+  (interface_declaration)
+  (enum_declaration)
+  (internal_module)
+] @context
@@ -0,0 +1,19 @@
+; inherits: ecma
+
+(abstract_class_declaration
+  name: (type_identifier) @name) @definition.class
+
+(interface_declaration
+  name: (type_identifier) @name) @definition.interface
+
+(type_alias_declaration
+  name: (type_identifier) @name) @definition.type
+
+(enum_declaration
+  name: (identifier) @name) @definition.enum
+
+(internal_module
+  name: (_) @name) @definition.module
+
+(abstract_method_signature
+  name: (_) @name) @definition.method
@@ -0,0 +1,17 @@
+; inherits: ecma
+
+; Classes
+[
+  (abstract_class_declaration)
+  (interface_declaration)
+] @class.outer
+
+((abstract_class_declaration
+  (class_body) @class.inner)
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
+
+((interface_declaration
+  (interface_body) @class.inner)
+  (#offset! @class.inner 0 1 0 -1)
+  (#trim! @class.inner 1 1 1 1))
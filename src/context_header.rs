@@ -0,0 +1,192 @@
+use std::{ops::Range, sync::LazyLock};
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    form::FormId,
+    text::{Tagger, Text, TextMut, TextState, txt},
+    ui::{PushSpecs, Side, Widget},
+};
+
+use crate::{TsHandle, parser};
+
+/// Adds the command used for toggling the [`ContextHeader`]
+pub(crate) fn add_context_header_command() {
+    cmd::add("context-header", |pa: &mut Pass| -> CmdResult {
+        let handle = context::current_buffer(pa);
+
+        let header = handle
+            .get_related::<ContextHeader>(pa)
+            .next()
+            .map(|(header, _)| header);
+        if let Some(header) = header {
+            header.close(pa)?;
+            return Ok(None);
+        }
+
+        if handle.get_ts_parser(pa).is_none() {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        }
+
+        let specs = PushSpecs {
+            side: Side::Above,
+            height: Some(0.0),
+            hidden: true,
+            ..PushSpecs::default()
+        };
+        handle.push_inner_widget(pa, ContextHeader::new(handle.clone()), specs);
+
+        Ok(None)
+    })
+    .doc(
+        txt!("Toggles a header with the scopes around the top of the buffer"),
+        None,
+    );
+}
+
+/// A [`Widget`] that pins the scopes around the top of a [`Buffer`]
+///
+/// For every scope that started above the first printed line, the
+/// first line of that scope is shown, highlighted like in the
+/// `Buffer`. The scopes come from the `context.scm` query of the
+/// language, or from its `folds.scm` query, if there is none.
+///
+/// If there are more scopes than the maximum number of lines, the
+/// innermost ones are shown.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+pub struct ContextHeader {
+    text: Text,
+    buffer: Handle,
+    max_lines: usize,
+    lines: Vec<usize>,
+    text_state: Option<TextState>,
+}
+
+impl ContextHeader {
+    /// Returns a new `ContextHeader`, for the scopes of a [`Buffer`]
+    ///
+    /// [`Buffer`]: duat_core::buffer::Buffer
+    pub fn new(buffer: Handle) -> Self {
+        Self {
+            text: Text::default(),
+            buffer,
+            max_lines: 5,
+            lines: Vec::new(),
+            text_state: None,
+        }
+    }
+
+    /// Sets the maximum number of lines to show
+    ///
+    /// By default, this is `5`.
+    pub fn with_max_lines(self, max_lines: usize) -> Self {
+        Self { max_lines, ..self }
+    }
+}
+
+impl Widget for ContextHeader {
+    fn update(pa: &mut Pass, handle: &Handle<Self>) {
+        let buffer = handle.read(pa).buffer.clone();
+        if buffer.is_closed(pa) {
+            _ = handle.close(pa);
+            return;
+        }
+
+        let (last_lines, last_state, max_lines) = {
+            let header = handle.read(pa);
+            (header.lines.clone(), header.text_state, header.max_lines)
+        };
+        let top = buffer.start_points(pa).real.line();
+
+        let Some((parser, buf)) = parser::sync_parse(pa, &buffer) else {
+            return;
+        };
+
+        let bytes = buf.bytes();
+        let text_state = buf.text().text_state();
+
+        let byte = bytes.point_at_line(top).byte();
+        let mut lines: Vec<usize> = parser
+            .context_on(byte, bytes)
+            .into_iter()
+            .map(|scope| bytes.point_at_byte(scope.start).line())
+            .filter(|line| *line < top)
+            .collect();
+        lines.dedup();
+        lines.drain(..lines.len().saturating_sub(max_lines));
+
+        if lines == last_lines && last_state == Some(text_state) {
+            handle.read(pa).buffer.declare_as_read();
+            return;
+        }
+
+        let mut builder = Text::builder();
+        let mut tags: Vec<(Range<usize>, FormId, u8)> = Vec::new();
+        let mut start = 0;
+
+        for (i, line) in lines.iter().enumerate() {
+            let range = bytes.line(*line).byte_range();
+            let line_str = bytes[range.clone()].to_string();
+            let line_str = line_str.trim_end_matches(['\n', '\r']);
+            let range = range.start..range.start + line_str.len();
+
+            if i > 0 {
+                builder.push("\n");
+                start += 1;
+            }
+            builder.push(line_str);
+
+            for cap in parser.highlight_captures_in(range.clone(), bytes) {
+                let cap_range = cap.range.start.max(range.start)..cap.range.end.min(range.end);
+                let shifted =
+                    cap_range.start - range.start + start..cap_range.end - range.start + start;
+                tags.push((shifted, cap.form(), cap.priority()));
+            }
+
+            start += line_str.len();
+        }
+
+        let mut text = builder.build();
+        let tagger = context_header_tagger();
+        for (range, form, priority) in tags {
+            text.insert_tag(tagger, range, form.to_tag(priority));
+        }
+
+        let height = lines.len();
+        let header = handle.write(pa);
+        header.text = text;
+        header.lines = lines;
+        header.text_state = Some(text_state);
+
+        if height != last_lines.len() {
+            let area = handle.area();
+            if height == 0 {
+                _ = area.hide(pa);
+            } else {
+                _ = area.set_height(pa, height as f32);
+                _ = area.reveal(pa);
+            }
+        }
+
+        handle.read(pa).buffer.declare_as_read();
+    }
+
+    fn needs_update(&self, pa: &Pass) -> bool {
+        self.buffer.has_changed(pa)
+    }
+
+    fn text(&self) -> &Text {
+        &self.text
+    }
+
+    fn text_mut(&mut self) -> TextMut<'_> {
+        self.text.as_mut()
+    }
+}
+
+fn context_header_tagger() -> Tagger {
+    static TAGGER: LazyLock<Tagger> = Tagger::new_static();
+    *TAGGER
+}
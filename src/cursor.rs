@@ -1,47 +1,137 @@
-use duat_core::text::Bytes;
+use std::ops::Range;
+
 use tree_sitter::{Node, TreeCursor};
 
-#[allow(unused)]
+/// A wrapper around a [`TreeCursor`], for navigation of a tree
 pub struct Cursor<'a> {
-    bytes: &'a Bytes,
     cursor: TreeCursor<'a>,
 }
 
 #[allow(unused)]
-impl Cursor<'_> {
-    pub fn node(&self) -> Node<'_> {
+impl<'a> Cursor<'a> {
+    /// Returns a new `Cursor`, starting on a [`Node`]
+    ///
+    /// The `Cursor` can't go above this `Node`, so it should usually
+    /// be the root of a tree.
+    pub fn new(node: Node<'a>) -> Self {
+        Self { cursor: node.walk() }
+    }
+
+    pub fn node(&self) -> Node<'a> {
         self.cursor.node()
     }
 
-    pub fn parent(&mut self) -> Option<Node<'_>> {
+    pub fn parent(&mut self) -> Option<Node<'a>> {
         self.cursor.goto_parent().then(|| self.cursor.node())
     }
 
-    pub fn first_child(&mut self) -> Option<Node<'_>> {
+    pub fn first_child(&mut self) -> Option<Node<'a>> {
         self.cursor.goto_first_child().then(|| self.cursor.node())
     }
 
-    pub fn last_child(&mut self) -> Option<Node<'_>> {
+    pub fn last_child(&mut self) -> Option<Node<'a>> {
         self.cursor.goto_last_child().then(|| self.cursor.node())
     }
 
-    pub fn child_with_byte(&mut self, byte: usize) -> Option<(usize, Node<'_>)> {
+    pub fn child_with_byte(&mut self, byte: usize) -> Option<(usize, Node<'a>)> {
         self.cursor
             .goto_first_child_for_byte(byte)
             .map(|i| (i, self.cursor.node()))
     }
 
-    pub fn prev_sibling(&mut self) -> Option<Node<'_>> {
+    pub fn prev_sibling(&mut self) -> Option<Node<'a>> {
         self.cursor
             .goto_previous_sibling()
             .then(|| self.cursor.node())
     }
 
-    pub fn next_sibling(&mut self) -> Option<Node<'_>> {
+    pub fn next_sibling(&mut self) -> Option<Node<'a>> {
         self.cursor.goto_next_sibling().then(|| self.cursor.node())
     }
 
     pub fn goto_descendant(&mut self, index: usize) {
         self.cursor.goto_descendant(index);
     }
+
+    /// Goes up to the first named ancestor
+    pub fn named_parent(&mut self) -> Option<Node<'a>> {
+        while let Some(parent) = self.parent() {
+            if parent.is_named() {
+                return Some(parent);
+            }
+        }
+
+        None
+    }
+
+    /// Goes to the first named child
+    pub fn first_named_child(&mut self) -> Option<Node<'a>> {
+        let child = self.first_child()?;
+        if child.is_named() {
+            return Some(child);
+        }
+
+        let child = self.next_named_sibling();
+        if child.is_none() {
+            self.cursor.goto_parent();
+        }
+
+        child
+    }
+
+    /// Goes to the next named sibling
+    pub fn next_named_sibling(&mut self) -> Option<Node<'a>> {
+        let start = self.cursor.clone();
+        while let Some(sibling) = self.next_sibling() {
+            if sibling.is_named() {
+                return Some(sibling);
+            }
+        }
+
+        self.cursor.reset_to(&start);
+        None
+    }
+
+    /// Goes to the previous named sibling
+    pub fn prev_named_sibling(&mut self) -> Option<Node<'a>> {
+        let start = self.cursor.clone();
+        while let Some(sibling) = self.prev_sibling() {
+            if sibling.is_named() {
+                return Some(sibling);
+            }
+        }
+
+        self.cursor.reset_to(&start);
+        None
+    }
+
+    /// Goes down to the smallest named [`Node`] that contains a
+    /// [`Range`]
+    ///
+    /// Returns [`None`] if there is no such `Node`.
+    pub fn goto_named_containing(&mut self, range: Range<usize>) -> Option<Node<'a>> {
+        let node = self.goto_smallest_containing(range.clone());
+        let node = if node.is_named() {
+            node
+        } else {
+            self.named_parent()?
+        };
+
+        (node.start_byte() <= range.start && range.end <= node.end_byte()).then_some(node)
+    }
+
+    /// Goes down to the smallest [`Node`] that contains a [`Range`]
+    ///
+    /// If the current `Node` doesn't contain the `Range`, the
+    /// `Cursor` stays on it.
+    pub fn goto_smallest_containing(&mut self, range: Range<usize>) -> Node<'a> {
+        while let Some((_, child)) = self.child_with_byte(range.start) {
+            if child.start_byte() > range.start || child.end_byte() < range.end {
+                self.cursor.goto_parent();
+                break;
+            }
+        }
+
+        self.node()
+    }
 }
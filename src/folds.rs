@@ -0,0 +1,235 @@
+use std::{ops::Range, sync::LazyLock};
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context,
+    data::Pass,
+    text::{Bytes, Conceal, Ghost, Tagger, Tags, txt},
+};
+use tree_sitter::InputEdit;
+
+use crate::TsHandle;
+
+/// Adds the commands used for folding
+pub(crate) fn add_fold_commands() {
+    fn set_folds(pa: &mut Pass, action: FoldAction) -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_set_folds(pa, .., action) {
+            Some(0) => Err(txt!("No folds under the carets")),
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    }
+
+    fn set_all_folds(pa: &mut Pass, is_closed: bool) -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_set_all_folds(pa, is_closed) {
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    }
+
+    cmd::add("fold-open", |pa: &mut Pass| set_folds(pa, FoldAction::Open))
+        .doc(txt!("Opens the fold under each caret"), None);
+    cmd::add("fold-close", |pa: &mut Pass| {
+        set_folds(pa, FoldAction::Close)
+    })
+    .doc(txt!("Closes the fold under each caret"), None);
+    cmd::add("fold-toggle", |pa: &mut Pass| {
+        set_folds(pa, FoldAction::Toggle)
+    })
+    .doc(txt!("Toggles the fold under each caret"), None);
+    cmd::add("fold-open-all", |pa: &mut Pass| set_all_folds(pa, false))
+        .doc(txt!("Opens every fold in the buffer"), None);
+    cmd::add("fold-close-all", |pa: &mut Pass| set_all_folds(pa, true))
+        .doc(txt!("Closes every fold in the buffer"), None);
+}
+
+/// What to do with the [`Fold`] under a caret
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FoldAction {
+    /// Opens the outermost closed [`Fold`]
+    Open,
+    /// Closes the innermost open [`Fold`]
+    Close,
+    /// Opens a closed [`Fold`], or closes an open one
+    Toggle,
+}
+
+/// A foldable region of a [`Buffer`], taken from `@fold` captures
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fold {
+    range: Range<usize>,
+    is_closed: bool,
+}
+
+impl Fold {
+    /// The byte [`Range`] of the folded node
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Wether this `Fold` is currently closed
+    pub fn is_closed(&self) -> bool {
+        self.is_closed
+    }
+}
+
+/// All [`Fold`]s of a [`Parser`], including those of injections
+///
+/// [`Parser`]: crate::Parser
+#[derive(Default, Debug)]
+pub(crate) struct Folds(Vec<Fold>);
+
+impl Folds {
+    /// Returns an [`Iterator`] over the [`Fold`]s
+    pub fn iter(&self) -> std::slice::Iter<'_, Fold> {
+        self.0.iter()
+    }
+
+    /// Replaces all [`Fold`]s that start within a [`Range`]
+    ///
+    /// Closed `Fold`s that start on the same byte as a new one stay
+    /// closed. Returns `true` if any closed `Fold` was affected.
+    pub fn replace_on(&mut self, range: Range<usize>, mut new: Vec<Range<usize>>) -> bool {
+        new.sort_unstable_by(|lhs, rhs| lhs.start.cmp(&rhs.start).then(rhs.end.cmp(&lhs.end)));
+        new.dedup();
+
+        let old: Vec<Fold> = self
+            .0
+            .extract_if(.., |fold| range.contains(&fold.range.start))
+            .collect();
+        let mut closed_changed = false;
+
+        for new_range in new {
+            let is_closed = old
+                .iter()
+                .any(|fold| fold.is_closed && fold.range.start == new_range.start);
+
+            let i = self
+                .0
+                .partition_point(|fold| fold.range.start <= new_range.start);
+            self.0.insert(i, Fold { range: new_range, is_closed });
+        }
+
+        for fold in old.into_iter().filter(|fold| fold.is_closed) {
+            closed_changed |= !self.0.contains(&fold);
+        }
+
+        closed_changed
+    }
+
+    /// Opens or closes the [`Fold`]s that intersect a line
+    ///
+    /// Returns `true` if any `Fold` was changed.
+    pub fn set_on_line(&mut self, line: Range<usize>, action: FoldAction) -> bool {
+        let on_line = |fold: &&Fold| fold.range.start < line.end && line.start < fold.range.end;
+
+        // Closed folds are opened from the outside, since those are the
+        // ones that are actually visible.
+        let outermost_closed = self
+            .0
+            .iter()
+            .filter(on_line)
+            .filter(|fold| fold.is_closed)
+            .max_by_key(|fold| fold.range.len())
+            .cloned();
+
+        let fold = match (action, outermost_closed) {
+            (FoldAction::Open | FoldAction::Toggle, Some(fold)) => fold,
+            (FoldAction::Open, None) => return false,
+            (FoldAction::Close | FoldAction::Toggle, _) => {
+                let Some(fold) = self
+                    .0
+                    .iter()
+                    .filter(on_line)
+                    .filter(|fold| !fold.is_closed)
+                    .min_by_key(|fold| fold.range.len())
+                else {
+                    return false;
+                };
+                fold.clone()
+            }
+        };
+
+        let fold = self.0.iter_mut().find(|f| **f == fold).unwrap();
+        fold.is_closed = !fold.is_closed;
+        true
+    }
+
+    /// Opens or closes every [`Fold`]
+    pub fn set_all(&mut self, is_closed: bool) {
+        for fold in self.0.iter_mut() {
+            fold.is_closed = is_closed;
+        }
+    }
+
+    /// Shifts the [`Fold`]s through an [`InputEdit`]
+    ///
+    /// `Fold`s that would become empty are removed.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        let shift = edit.new_end_byte as i32 - edit.old_end_byte as i32;
+        let shift_start = |byte: usize| {
+            if byte >= edit.old_end_byte {
+                (byte as i32 + shift) as usize
+            } else {
+                byte.min(edit.start_byte)
+            }
+        };
+        let shift_end = |byte: usize| {
+            if byte >= edit.old_end_byte {
+                (byte as i32 + shift) as usize
+            } else if byte > edit.start_byte {
+                edit.new_end_byte.min(byte)
+            } else {
+                byte
+            }
+        };
+
+        self.0.retain_mut(|fold| {
+            fold.range = shift_start(fold.range.start)..shift_end(fold.range.end);
+            !fold.range.is_empty()
+        });
+    }
+
+    /// Conceals all closed [`Fold`]s, revealing the open ones
+    ///
+    /// The first line of a closed `Fold` is kept visible, and the
+    /// rest is replaced by a [`Ghost`].
+    pub fn retag(&self, tags: &mut Tags, bytes: &Bytes) {
+        let tagger = fold_tagger();
+        tags.remove(tagger, ..);
+
+        let mut last_end = 0;
+
+        for fold in self.0.iter().filter(|fold| fold.is_closed) {
+            // Nested closed folds are already concealed.
+            if fold.range.start < last_end {
+                continue;
+            }
+
+            let start = bytes.point_at_byte(fold.range.start);
+            let end = bytes.point_at_byte(fold.range.end.saturating_sub(1).max(fold.range.start));
+
+            if end.line() == start.line() {
+                continue;
+            }
+
+            let conceal_start = bytes.line(start.line()).range().end.byte() - 1;
+            let conceal_end = bytes.line(end.line()).range().end.byte() - 1;
+
+            tags.insert(tagger, conceal_start..conceal_end, Conceal);
+            tags.insert(tagger, conceal_start, Ghost::new(txt!("[fold]…")));
+
+            last_end = conceal_end;
+        }
+    }
+}
+
+/// The [`Tagger`] for folds
+pub(crate) fn fold_tagger() -> Tagger {
+    static TAGGER: LazyLock<Tagger> = Tagger::new_static();
+    *TAGGER
+}
@@ -0,0 +1,135 @@
+use std::ops::Range;
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context,
+    data::Pass,
+    form::FormId,
+    text::{Text, txt},
+};
+
+use crate::TsHandle;
+
+/// Adds the command used for inspecting highlight captures
+pub(crate) fn add_inspect_command() {
+    cmd::add("inspect-captures", |pa: &mut Pass| -> CmdResult {
+        let handle = context::current_buffer(pa);
+        let byte = handle.selections(pa).main().caret().byte();
+
+        let Some(captures) = handle.ts_highlight_captures(pa, byte) else {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        };
+
+        if captures.is_empty() {
+            return Err(txt!("No highlight captures under the caret"));
+        }
+
+        let mut builder = Text::builder();
+        for (i, cap) in captures.iter().enumerate() {
+            if i > 0 {
+                builder.push("\n");
+            }
+            builder.push(txt!(
+                "[a]@{}[] on [a]{}[], layer {}: form [a]{}[], priority {}, ",
+                cap.name(),
+                cap.lang(),
+                cap.layer(),
+                cap.form().name(),
+                cap.priority(),
+            ));
+            match (cap.source(), cap.pattern_index()) {
+                (Some((lang, line)), _) => {
+                    builder.push(txt!("pattern at [a]{lang}/highlights.scm[], line {line}"))
+                }
+                (None, Some(pattern_index)) => builder.push(txt!("pattern {pattern_index}")),
+                (None, None) => builder.push(txt!("from [a]locals.scm")),
+            }
+        }
+
+        Ok(Some(builder.build()))
+    })
+    .doc(
+        txt!("Lists the highlight captures under the main caret"),
+        None,
+    );
+}
+
+/// A `highlights.scm` capture, along with the form it resolved to
+///
+/// With semantic highlighting enabled, this can also be a reference
+/// from `locals.scm`, with the form of its definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HighlightCapture {
+    pub(crate) range: Range<usize>,
+    pub(crate) name: &'static str,
+    pub(crate) lang: &'static str,
+    pub(crate) layer: usize,
+    pub(crate) form: FormId,
+    pub(crate) priority: u8,
+    pub(crate) pattern_index: Option<usize>,
+    pub(crate) source: Option<(&'static str, usize)>,
+}
+
+impl HighlightCapture {
+    /// The byte [`Range`] of the capture, with directives applied
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// The name of the capture, like `"function.call"`
+    ///
+    /// This is `"local.reference"` for semantic forms.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The language of the tree that the capture came from
+    pub fn lang(&self) -> &'static str {
+        self.lang
+    }
+
+    /// How deeply nested the injection of the capture is
+    ///
+    /// This is `0` for captures of the main language of the
+    /// [`Buffer`], `1` for those of its injections, and so on.
+    ///
+    /// [`Buffer`]: duat_core::buffer::Buffer
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// The [`FormId`] that the capture resolved to
+    ///
+    /// This is the form named `"{name}.{lang}"`, which, unless set,
+    /// inherits from the form of the capture's name.
+    pub fn form(&self) -> FormId {
+        self.form
+    }
+
+    /// The priority of the form's tag
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// The index of the pattern in the query
+    ///
+    /// This counts the patterns of inherited queries, which come
+    /// first, so see [`HighlightCapture::source`] for where the
+    /// pattern actually is.
+    ///
+    /// Returns [`None`] for semantic forms, since they don't come
+    /// from a pattern of `highlights.scm`.
+    pub fn pattern_index(&self) -> Option<usize> {
+        self.pattern_index
+    }
+
+    /// The `highlights.scm` file and line of the pattern
+    ///
+    /// The file is given by its language, which may be one that the
+    /// capture's language inherits from. Lines start at 1.
+    ///
+    /// Returns [`None`] for semantic forms.
+    pub fn source(&self) -> Option<(&'static str, usize)> {
+        self.source
+    }
+}
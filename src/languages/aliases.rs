@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+};
+
+use duat_filetype::FileType;
+
+use super::list::LANGUAGE_OPTIONS;
+
+static USER_ALIASES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Mutex::default);
+
+#[rustfmt::skip]
+const INFO_STRING_ALIASES: &[(&str, &str)] = &[
+    ("c++", "cpp"), ("cs", "c_sharp"), ("csharp", "c_sharp"), ("ex", "elixir"),
+    ("exs", "elixir"), ("golang", "go"), ("hs", "haskell"), ("js", "javascript"),
+    ("jsonc", "json"), ("kt", "kotlin"), ("md", "markdown"), ("ml", "ocaml"),
+    ("py", "python"), ("python3", "python"), ("rb", "ruby"), ("rs", "rust"),
+    ("sh", "bash"), ("shell", "bash"), ("ts", "typescript"), ("yml", "yaml"),
+    ("zsh", "bash"),
+];
+
+#[rustfmt::skip]
+const MIMETYPE_ALIASES: &[(&str, &str)] = &[
+    ("importmap", "json"), ("module", "javascript"), ("application/ecmascript", "javascript"),
+    ("application/javascript", "javascript"), ("application/json", "json"),
+    ("application/ld+json", "json"), ("text/ecmascript", "javascript"),
+    ("text/javascript", "javascript"), ("text/typescript", "typescript"),
+];
+
+/// Adds an alias for a language
+///
+/// This alias will be used when resolving the language of
+/// injections from the info strings of code blocks, like in
+/// Markdown, as well as from mimetypes, like in the `type`
+/// attribute of HTML's `<script>` tags.
+///
+/// User aliases take precedence over the default ones, so you can
+/// also use this function in order to change those.
+pub fn add_lang_alias(alias: impl ToString, lang: impl ToString) {
+    let alias = alias.to_string().to_lowercase();
+    USER_ALIASES.lock().unwrap().insert(alias, lang.to_string());
+}
+
+/// The language of an info string, like `rs` or `python
+/// {.numberLines}`
+pub fn lang_from_info_string(info_string: &str) -> Option<String> {
+    let name = info_string
+        .split_whitespace()
+        .next()?
+        .trim_matches(['{', '}', '.'])
+        .to_lowercase();
+
+    resolve_name(&name, INFO_STRING_ALIASES)
+}
+
+/// The language of a mimetype, like `text/javascript`
+pub fn lang_from_mimetype(mimetype: &str) -> Option<String> {
+    let mimetype = mimetype.trim().to_lowercase();
+
+    if let Some(lang) = user_alias(&mimetype).or_else(|| alias_in(&mimetype, MIMETYPE_ALIASES)) {
+        return Some(lang);
+    }
+
+    let (_, subtype) = mimetype.split_once('/')?;
+    resolve_name(subtype, INFO_STRING_ALIASES)
+}
+
+/// Resolves a name to a language
+///
+/// The name is looked up in the user aliases, then in the default
+/// ones. If it is not an alias, it should be either a key of
+/// [`LANGUAGE_OPTIONS`] or the extension of a filetype.
+fn resolve_name(name: &str, aliases: &[(&str, &str)]) -> Option<String> {
+    if let Some(lang) = user_alias(name).or_else(|| alias_in(name, aliases)) {
+        Some(lang)
+    } else if LANGUAGE_OPTIONS.contains_key(name) {
+        Some(name.to_string())
+    } else {
+        PathBuf::from(format!("injection.{name}"))
+            .filetype()
+            .map(str::to_string)
+    }
+}
+
+fn user_alias(name: &str) -> Option<String> {
+    USER_ALIASES.lock().unwrap().get(name).cloned()
+}
+
+fn alias_in(name: &str, aliases: &[(&str, &str)]) -> Option<String> {
+    aliases
+        .iter()
+        .find_map(|(alias, lang)| (*alias == name).then(|| lang.to_string()))
+}
@@ -12,6 +12,7 @@ use indoc::formatdoc;
 use libloading::Library;
 use tree_sitter::Language;
 
+pub use self::aliases::{add_lang_alias, lang_from_info_string, lang_from_mimetype};
 use self::list::LANGUAGE_OPTIONS;
 
 static FAILED_COPILATION: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
@@ -19,6 +20,7 @@ static COMPILATIONS: LazyLock<Mutex<HashMap<String, Compilation>>> = LazyLock::n
 
 type Compilation = (JoinHandle<Option<ExitStatus>>, Vec<Handle>);
 
+mod aliases;
 mod list;
 
 pub fn get_language(filetype: &str, handle: &Handle) -> Option<Language> {
@@ -185,8 +187,12 @@ pub fn get_language(filetype: &str, handle: &Handle) -> Option<Language> {
 }
 
 fn get_parsers_dir() -> Option<PathBuf> {
-    let workspace_dir = duat_core::utils::plugin_dir("duat-treesitter").ok()?;
-    let parsers_dir = workspace_dir.join("parsers");
+    let parsers_dir = match crate::options().parsers_dir.clone() {
+        Some(parsers_dir) => parsers_dir,
+        None => duat_core::utils::plugin_dir("duat-treesitter")
+            .ok()?
+            .join("parsers"),
+    };
     fs::create_dir_all(&parsers_dir).ok()?;
 
     Some(parsers_dir)
@@ -0,0 +1,157 @@
+use std::ops::Range;
+
+use duat_core::{buffer::BufferParts, text::Bytes};
+
+use crate::options;
+
+/// How a [`Parser`] deals with large [`Buffer`]s
+///
+/// A `Buffer` is considered large if it has more bytes than
+/// [`TreeSitter::large_file_bytes`], or if any of its lines is
+/// longer than [`TreeSitter::long_line_len`]. Once it is no longer
+/// large, it goes back to being fully parsed.
+///
+/// [`Parser`]: crate::Parser
+/// [`Buffer`]: duat_core::buffer::Buffer
+/// [`TreeSitter::large_file_bytes`]: crate::TreeSitter::large_file_bytes
+/// [`TreeSitter::long_line_len`]: crate::TreeSitter::long_line_len
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LargeFileMode {
+    /// Only parse a window of lines around the printed ones
+    ///
+    /// Injections are not parsed in this mode.
+    Window {
+        /// How many lines to parse above and below the printed ones
+        lines: usize,
+    },
+    /// Parse the whole `Buffer`, but without injections
+    NoInjections,
+    /// Turn tree-sitter off for the `Buffer`, with a notice
+    Disable,
+}
+
+impl Default for LargeFileMode {
+    fn default() -> Self {
+        Self::Window { lines: 500 }
+    }
+}
+
+/// The long lines of a [`Buffer`], kept up to date with its changes
+///
+/// This is used in order to tell wether a `Buffer` is large without
+/// going over all of its lines whenever it changes.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+#[derive(Default)]
+pub(crate) struct LongLines(Vec<Range<usize>>);
+
+impl LongLines {
+    /// Finds every long line in the [`Bytes`]
+    pub fn new(bytes: &Bytes) -> Self {
+        Self(long_lines_in(bytes, 0..bytes.len().line() + 1).collect())
+    }
+
+    /// Updates the long lines with the [`Change`]s of a [`Buffer`]
+    ///
+    /// Only the lines touched by the `Change`s are checked again.
+    ///
+    /// [`Change`]: duat_core::buffer::Change
+    /// [`Buffer`]: duat_core::buffer::Buffer
+    pub fn update(&mut self, parts: &BufferParts) {
+        // Byte ranges that were changed, in terms of the latest Bytes.
+        let mut changed: Vec<Range<usize>> = Vec::new();
+
+        for change in parts.changes.clone() {
+            let start = change.start().byte();
+            let taken = change.taken_end().byte();
+            let added = change.added_end().byte();
+
+            let touches = |range: &Range<usize>| range.start <= taken && start <= range.end;
+            let shift = |byte: usize| byte - taken + added;
+
+            self.0.retain(|line| !touches(line));
+
+            let mut new = start..added;
+            changed.retain(|range| {
+                if touches(range) {
+                    new.start = new.start.min(range.start);
+                    if range.end > taken {
+                        new.end = new.end.max(shift(range.end));
+                    }
+                }
+                !touches(range)
+            });
+
+            for range in self.0.iter_mut().chain(changed.iter_mut()) {
+                if range.start > taken {
+                    *range = shift(range.start)..shift(range.end);
+                }
+            }
+
+            changed.push(new);
+        }
+
+        let bytes = parts.bytes;
+        let len = bytes.len().byte();
+        for range in changed {
+            let start = bytes.point_at_byte(range.start.min(len)).line();
+            let end = bytes.point_at_byte(range.end.min(len)).line() + 1;
+            self.0.extend(long_lines_in(bytes, start..end));
+        }
+
+        self.0.sort_unstable_by_key(|line| line.start);
+        self.0.dedup();
+    }
+
+    /// Wether the [`Bytes`] are large, given the plugin's options
+    pub fn is_large(&self, bytes: &Bytes) -> bool {
+        bytes.len().byte() > options().large_file_bytes || !self.0.is_empty()
+    }
+}
+
+/// The byte [`Range`]s of the long lines within some lines
+fn long_lines_in(bytes: &Bytes, lines: Range<usize>) -> impl Iterator<Item = Range<usize>> {
+    let line_count = bytes.len().line() + 1;
+    let long_line_len = options().long_line_len;
+
+    (lines.start.min(line_count)..lines.end.min(line_count))
+        .map(|line| bytes.line(line).byte_range())
+        .filter(move |range| range.len() > long_line_len)
+}
+
+/// The byte [`Range`]s to parse in [`LargeFileMode::Window`]
+///
+/// This includes the visible ranges, as well as `lines` lines above
+/// and below each of them. Windows that overlap are merged, so a
+/// `Buffer` shown in many places gets one window per place.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+pub(crate) fn windows_around(
+    bytes: &Bytes,
+    visible_ranges: &[Range<usize>],
+    lines: usize,
+) -> Vec<Range<usize>> {
+    let mut windows: Vec<Range<usize>> = Vec::new();
+
+    for range in visible_ranges {
+        let first_line = bytes
+            .point_at_byte(range.start)
+            .line()
+            .saturating_sub(lines);
+        let last_line = bytes.point_at_byte(range.end).line() + lines + 1;
+
+        let start = bytes.point_at_line(first_line).byte();
+        let end = if last_line > bytes.len().line() {
+            bytes.len().byte()
+        } else {
+            bytes.point_at_line(last_line).byte()
+        };
+
+        match windows.last_mut() {
+            Some(last) if last.end >= start => last.end = last.end.max(end),
+            _ => windows.push(start..end),
+        }
+    }
+
+    windows
+}
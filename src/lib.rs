@@ -1,9 +1,14 @@
 //! A [tree-sitter] implementation for Duat
 //!
-//! `duat-treesitter` currently does two things:
+//! `duat-treesitter` currently does these things:
 //!
 //! * Syntax highlighting
 //! * Indentation calculation
+//! * Code folding
+//! * Local scope resolution
+//! * Text objects
+//! * Sticky context headers
+//! * Symbol outlines
 //!
 //! # Installation
 //!
@@ -27,9 +32,10 @@
 use std::{
     collections::{HashMap, HashSet},
     fs,
-    ops::RangeBounds,
+    ops::{Range, RangeBounds},
     path::{Path, PathBuf},
-    sync::{LazyLock, Mutex},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::Duration,
 };
 
 use duat_core::{
@@ -38,18 +44,46 @@ use duat_core::{
     context::{self, Handle},
     data::Pass,
     form::{self, Form},
-    text::{Builder, Text, txt},
+    mode::Selections,
+    text::{Bytes, Text, txt},
     ui::Widget,
 };
-use tree_sitter::{Language, Node, Query};
+use tree_sitter::{Language, Query};
 
-use crate::languages::get_language;
-pub use crate::parser::Parser;
+pub use crate::{
+    context_header::ContextHeader,
+    folds::{Fold, FoldAction},
+    inspect::HighlightCapture,
+    languages::add_lang_alias,
+    large_file::LargeFileMode,
+    locals::{Definition, Locals},
+    motions::NodeMotion,
+    outline::{Outline, Symbol},
+    parser::{FullyParsed, Parser},
+    query::Capture,
+    tree_view::TreeView,
+};
+use crate::{languages::get_language, motions::Swap};
 
+mod context_header;
 mod cursor;
+mod folds;
+mod inspect;
 mod languages;
+mod large_file;
+mod locals;
+mod motions;
+mod outline;
 mod parser;
+mod playground;
+mod predicates;
+mod query;
+mod replace;
+mod selections;
+mod snapshot;
 mod tree;
+mod tree_view;
+mod worker;
 
 /// The [tree-sitter] plugin for Duat
 ///
@@ -60,95 +94,339 @@ mod tree;
 /// reads updates the inner syntax tree when the [`Text`] reports any
 /// changes.
 ///
+/// The plugin can be configured before being plugged, by calling
+/// builder methods like [`TreeSitter::disable`] on
+/// [`TreeSitter::new`].
+///
 /// [tree-sitter]: https://tree-sitter.github.io/tree-sitter
 /// [`duat_treesitter::Parser`]: Parser
-#[derive(Default)]
-pub struct TreeSitter;
+pub struct TreeSitter {
+    parse_budget: Duration,
+    disabled: Vec<String>,
+    no_highlights: Vec<String>,
+    no_indents: Vec<String>,
+    no_injections: Vec<String>,
+    priorities: Vec<String>,
+    default_forms: bool,
+    queries_dir: Option<PathBuf>,
+    parsers_dir: Option<PathBuf>,
+    large_file_bytes: usize,
+    long_line_len: usize,
+    large_file_mode: LargeFileMode,
+}
+
+impl TreeSitter {
+    /// Returns a new `TreeSitter`, with the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long to work on the main thread before yielding
+    ///
+    /// Parsing happens on a separate thread, but finding injections
+    /// is done on the main thread, in slices of this duration, so
+    /// Duat stays responsive. By default, this is 3 milliseconds.
+    pub fn parse_budget(self, parse_budget: Duration) -> Self {
+        Self { parse_budget, ..self }
+    }
+
+    /// Disables tree-sitter for some languages
+    ///
+    /// This includes injections of those languages into others.
+    pub fn disable(mut self, langs: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.disabled
+            .extend(langs.into_iter().map(|lang| lang.to_string()));
+        self
+    }
+
+    /// Disables syntax highlighting for some languages
+    pub fn disable_highlights(mut self, langs: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.no_highlights
+            .extend(langs.into_iter().map(|lang| lang.to_string()));
+        self
+    }
+
+    /// Disables indentation calculation for some languages
+    ///
+    /// For these languages, [`TsHandle::ts_get_indentations`] will
+    /// return [`None`] for every line.
+    pub fn disable_indents(mut self, langs: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.no_indents
+            .extend(langs.into_iter().map(|lang| lang.to_string()));
+        self
+    }
+
+    /// Disables injections into some languages
+    ///
+    /// Languages injected into other languages are unaffected.
+    pub fn disable_injections(mut self, langs: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.no_injections
+            .extend(langs.into_iter().map(|lang| lang.to_string()));
+        self
+    }
+
+    /// Sets the priority of highlight captures, by prefix
+    ///
+    /// When many captures highlight the same text, the form of the
+    /// one whose name starts with a later prefix is drawn on top.
+    /// Captures that match no prefix are drawn above all others.
+    pub fn priorities(self, priorities: impl IntoIterator<Item = impl ToString>) -> Self {
+        let priorities = priorities.into_iter().map(|p| p.to_string()).collect();
+        Self { priorities, ..self }
+    }
+
+    /// Wether to set default forms for highlight captures
+    ///
+    /// Even if this is `false`, forms are still set for the widgets
+    /// of this plugin. By default, this is `true`.
+    pub fn default_forms(self, default_forms: bool) -> Self {
+        Self { default_forms, ..self }
+    }
+
+    /// Sets the directory where queries are read from
+    ///
+    /// If the directory doesn't exist, the default queries are
+    /// installed in it. Query files added by updates are installed
+    /// later on, but files that were deleted from it are not, which
+    /// is tracked by an `.installed` file. By default, this is a
+    /// `queries` directory inside of the plugin's directory.
+    pub fn queries_dir(self, queries_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            queries_dir: Some(queries_dir.into()),
+            ..self
+        }
+    }
+
+    /// Sets the directory where parsers are compiled to
+    ///
+    /// By default, this is a `parsers` directory inside of the
+    /// plugin's directory.
+    pub fn parsers_dir(self, parsers_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            parsers_dir: Some(parsers_dir.into()),
+            ..self
+        }
+    }
+
+    /// Above how many bytes a buffer is considered large
+    ///
+    /// Large buffers are dealt with according to the
+    /// [`LargeFileMode`]. By default, this is 4 MiB.
+    pub fn large_file_bytes(self, large_file_bytes: usize) -> Self {
+        Self { large_file_bytes, ..self }
+    }
+
+    /// Above how many bytes a line makes its buffer large
+    ///
+    /// This is meant for things like minified files, where a handful
+    /// of lines can be expensive to parse and highlight. By default,
+    /// this is 10000.
+    pub fn long_line_len(self, long_line_len: usize) -> Self {
+        Self { long_line_len, ..self }
+    }
+
+    /// How to deal with large buffers
+    ///
+    /// By default, this is [`LargeFileMode::Window`], with 500 lines
+    /// around the printed ones.
+    pub fn large_file_mode(self, large_file_mode: LargeFileMode) -> Self {
+        Self { large_file_mode, ..self }
+    }
+}
+
+impl Default for TreeSitter {
+    fn default() -> Self {
+        #[rustfmt::skip]
+        const PRIORITIES: &[&str] = &[
+            "markup", "operator", "comment", "string", "diff", "variable", "module", "label",
+            "character", "boolean", "number", "type", "attribute", "property", "function",
+            "constant", "constructor", "keyword", "punctuation",
+        ];
+
+        Self {
+            parse_budget: Duration::from_millis(3),
+            disabled: Vec::new(),
+            no_highlights: Vec::new(),
+            no_indents: Vec::new(),
+            no_injections: Vec::new(),
+            priorities: PRIORITIES.iter().map(|p| p.to_string()).collect(),
+            default_forms: true,
+            queries_dir: None,
+            parsers_dir: None,
+            large_file_bytes: 4 * 1024 * 1024,
+            long_line_len: 10_000,
+            large_file_mode: LargeFileMode::default(),
+        }
+    }
+}
 
 impl duat_core::Plugin for TreeSitter {
     fn plug(self, _: &Plugins) {
-        fn copy_dir_all(src: &include_dir::Dir, dst: impl AsRef<Path>) -> std::io::Result<()> {
-            fs::create_dir_all(&dst)?;
+        // Each file is only installed once, so files that were edited or
+        // deleted are left alone, but new files from updates are still
+        // installed. If `install` is false, files are only listed.
+        fn install_new(
+            src: &include_dir::Dir,
+            dst: &Path,
+            installed: &mut HashSet<PathBuf>,
+            install: bool,
+        ) -> std::io::Result<usize> {
+            let mut copied = 0;
             for entry in src.entries() {
                 if let Some(dir) = entry.as_dir() {
-                    copy_dir_all(dir, dst.as_ref().join(entry.path().file_name().unwrap()))?;
-                } else {
-                    fs::write(
-                        dst.as_ref().join(entry.path().file_name().unwrap()),
-                        entry.as_file().unwrap().contents(),
-                    )?
+                    copied += install_new(dir, dst, installed, install)?;
+                } else if installed.insert(entry.path().to_path_buf()) && install {
+                    let path = dst.join(entry.path());
+                    if !path.try_exists()? {
+                        fs::create_dir_all(path.parent().unwrap())?;
+                        fs::write(path, entry.as_file().unwrap().contents())?;
+                        copied += 1;
+                    }
                 }
             }
-            Ok(())
+            Ok(copied)
         }
 
         static QUERIES: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/queries");
 
-        let Ok(plugin_dir) = duat_core::utils::plugin_dir("duat-treesitter") else {
+        if OPTIONS.set(self).is_err() {
+            context::warn!("Tree-sitter was already plugged, ignoring new options");
+        }
+
+        let Ok(dest) = queries_dir() else {
             context::error!("No local directory, queries aren't installed");
             return;
         };
 
-        let dest = plugin_dir.join("queries");
+        let list = dest.join(".installed");
+        let install = |existed: bool| -> std::io::Result<usize> {
+            let mut installed: HashSet<PathBuf> = match fs::read_to_string(&list) {
+                Ok(list) => list.lines().map(PathBuf::from).collect(),
+                Err(_) => HashSet::new(),
+            };
+
+            // Without a list, deleted files can't be told apart from new
+            // ones, so the files of an existing directory are only listed.
+            let has_list = list.try_exists()?;
+            fs::create_dir_all(&dest)?;
+            let copied = install_new(&QUERIES, &dest, &mut installed, !existed || has_list)?;
+
+            let mut paths: Vec<String> = installed
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            paths.sort_unstable();
+            fs::write(&list, paths.join("\n"))?;
+
+            Ok(copied)
+        };
+
         match dest.try_exists() {
-            Ok(false) => match copy_dir_all(&QUERIES, &dest) {
-                Ok(_) => {
+            Ok(existed) => match install(existed) {
+                Ok(0) => {}
+                Ok(_) if !existed => {
                     context::info!("Installed tree-sitter queries at [buffer]{dest}");
                 }
+                Ok(copied) => {
+                    context::info!("Installed {copied} new tree-sitter queries at [buffer]{dest}");
+                }
                 Err(err) => {
                     context::info!(
                         "Failed to install tree-sitter queries at [buffer]{dest}: {err}"
                     );
                 }
             },
-            Ok(true) => {}
             Err(err) => {
                 context::warn!("Coudn't confirm existance of [buffer]{dest}: {err}")
             }
         }
 
+        if options().default_forms {
+            set_default_forms();
+        }
+
         form::set_many_weak!(
-            ("variable", Form::white()),
-            ("variable.builtin", Form::dark_yellow()),
-            ("constant", Form::grey()),
-            ("constant.builtin", Form::dark_yellow()),
-            ("module", Form::blue().italic()),
-            ("label", Form::green()),
-            ("string", Form::green()),
-            ("character", Form::dark_yellow()),
-            ("boolean", Form::dark_yellow()),
-            ("number", Form::dark_yellow()),
-            ("type", Form::yellow().italic()),
-            ("type.builtin", Form::yellow().reset()),
-            ("attribute", Form::green()),
-            ("property", Form::green()),
-            ("function", Form::blue().reset()),
-            ("constructor", Form::dark_yellow().reset()),
-            ("operator", Form::cyan()),
-            ("keyword", Form::magenta()),
-            ("punctuation.bracket", Form::grey()),
-            ("punctuation.delimiter", Form::grey()),
-            ("comment", Form::grey()),
-            ("comment.documentation", Form::grey().bold()),
-            ("markup.strong", Form::bold()),
-            ("markup.italic", Form::italic()),
-            ("markup.strikethrough", Form::crossed_out()),
-            ("markup.underline", Form::underlined()),
-            ("markup.heading", Form::blue().bold()),
-            ("markup.math", Form::yellow()),
-            ("markup.quote", Form::grey().italic()),
-            ("markup.link", Form::blue().underlined()),
-            ("markup.raw", Form::cyan()),
-            ("markup.list", Form::yellow()),
-            ("markup.list.checked", Form::green()),
-            ("markup.list.unchecked", Form::grey()),
-            ("diff.plus", Form::red()),
-            ("diff.delta", Form::blue()),
-            ("diff.minus", Form::green()),
             ("node.field", "variable.member"),
+            ("fold", Form::grey().italic()),
+            ("node.name", "function"),
+            ("coords", "number"),
+            ("selected.TreeView", Form::on_dark_grey()),
+            ("match.QueryPlayground", Form::underlined()),
+            ("default.ContextHeader", Form::on_dark_grey()),
+            ("symbol.kind", "keyword"),
+            ("symbol.name", "function"),
+            ("selected.Outline", Form::on_dark_grey()),
         );
 
         parser::add_parser_hook();
+        folds::add_fold_commands();
+        locals::add_semantic_command();
+        selections::add_selection_commands();
+        motions::add_motion_commands();
+        tree_view::add_tree_view_command();
+        inspect::add_inspect_command();
+        playground::add_playground_command();
+        replace::add_replace_command();
+        context_header::add_context_header_command();
+        outline::add_outline_command();
+    }
+}
+
+static OPTIONS: OnceLock<TreeSitter> = OnceLock::new();
+
+/// The options that the [`TreeSitter`] plugin was plugged with
+fn options() -> &'static TreeSitter {
+    OPTIONS.get_or_init(TreeSitter::default)
+}
+
+/// Sets the default forms of highlight captures
+fn set_default_forms() {
+    form::set_many_weak!(
+        ("variable", Form::white()),
+        ("variable.builtin", Form::dark_yellow()),
+        ("constant", Form::grey()),
+        ("constant.builtin", Form::dark_yellow()),
+        ("module", Form::blue().italic()),
+        ("label", Form::green()),
+        ("string", Form::green()),
+        ("character", Form::dark_yellow()),
+        ("boolean", Form::dark_yellow()),
+        ("number", Form::dark_yellow()),
+        ("type", Form::yellow().italic()),
+        ("type.builtin", Form::yellow().reset()),
+        ("attribute", Form::green()),
+        ("property", Form::green()),
+        ("function", Form::blue().reset()),
+        ("constructor", Form::dark_yellow().reset()),
+        ("operator", Form::cyan()),
+        ("keyword", Form::magenta()),
+        ("punctuation.bracket", Form::grey()),
+        ("punctuation.delimiter", Form::grey()),
+        ("comment", Form::grey()),
+        ("comment.documentation", Form::grey().bold()),
+        ("markup.strong", Form::bold()),
+        ("markup.italic", Form::italic()),
+        ("markup.strikethrough", Form::crossed_out()),
+        ("markup.underline", Form::underlined()),
+        ("markup.heading", Form::blue().bold()),
+        ("markup.math", Form::yellow()),
+        ("markup.quote", Form::grey().italic()),
+        ("markup.link", Form::blue().underlined()),
+        ("markup.raw", Form::cyan()),
+        ("markup.list", Form::yellow()),
+        ("markup.list.checked", Form::green()),
+        ("markup.list.unchecked", Form::grey()),
+        ("diff.plus", Form::red()),
+        ("diff.delta", Form::blue()),
+        ("diff.minus", Form::green()),
+    );
+}
+
+/// The directory where queries are read from
+fn queries_dir() -> Result<PathBuf, Text> {
+    match options().queries_dir.clone() {
+        Some(queries_dir) => Ok(queries_dir),
+        None => Ok(duat_core::utils::plugin_dir("duat-treesitter")?.join("queries")),
     }
 }
 
@@ -159,12 +437,21 @@ struct Queries<'a> {
     highlights: &'a Query,
     indents: &'a Query,
     injections: &'a Query,
+    folds: &'a Query,
+    locals: &'a Query,
+    textobjects: &'a Query,
+    context: &'a Query,
+    tags: &'a Query,
 }
 
 fn lang_parts_of(lang: &str, handle: &Handle) -> Option<LangParts<'static>> {
     static MAPS: LazyLock<Mutex<HashMap<&str, LangParts<'static>>>> = LazyLock::new(Mutex::default);
     static FAILED_PARTS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
 
+    if options().disabled.iter().any(|disabled| disabled == lang) {
+        return None;
+    }
+
     let mut maps = MAPS.lock().unwrap();
 
     if let Some(lang_parts) = maps.get(lang).copied() {
@@ -175,10 +462,44 @@ fn lang_parts_of(lang: &str, handle: &Handle) -> Option<LangParts<'static>> {
         let language: &'static Language = Box::leak(Box::new(get_language(lang, handle)?));
 
         let get_queries = || {
-            let highlights = query_from_path(lang, "highlights", language).ok()?;
-            let indents = query_from_path(lang, "indents", language).ok()?;
-            let injections = query_from_path(lang, "injections", language).ok()?;
-            Some(Queries { highlights, indents, injections })
+            let opts = options();
+            let enabled = |langs: &[String]| !langs.iter().any(|disabled| disabled == lang);
+            let empty = || &*Box::leak(Box::new(Query::new(language, "").unwrap()));
+
+            let highlights = match enabled(&opts.no_highlights) {
+                true => query_from_path(lang, "highlights", language).ok()?,
+                false => empty(),
+            };
+            let indents = match enabled(&opts.no_indents) {
+                true => query_from_path(lang, "indents", language).ok()?,
+                false => empty(),
+            };
+            let injections = match enabled(&opts.no_injections) {
+                true => query_from_path(lang, "injections", language).ok()?,
+                false => empty(),
+            };
+            // Broken optional queries shouldn't disable the whole language.
+            let optional = |kind| {
+                query_from_path(lang, kind, language).unwrap_or_else(|err| {
+                    context::error!("{err}");
+                    Box::leak(Box::new(Query::new(language, "").unwrap()))
+                })
+            };
+            let folds = optional("folds");
+            let locals = optional("locals");
+            let textobjects = optional("textobjects");
+            let context = optional("context");
+            let tags = optional("tags");
+            Some(Queries {
+                highlights,
+                indents,
+                injections,
+                folds,
+                locals,
+                textobjects,
+                context,
+                tags,
+            })
         };
 
         let Some(queries) = get_queries() else {
@@ -194,6 +515,17 @@ fn lang_parts_of(lang: &str, handle: &Handle) -> Option<LangParts<'static>> {
     }
 }
 
+/// The source of a [`Query`], put together from its inherited files
+struct QuerySource {
+    text: String,
+    /// The byte where each file starts, and its language
+    files: Vec<(usize, &'static str)>,
+}
+
+/// The sources of the [`Query`]s returned by [`query_from_path`]
+static QUERY_SOURCES: LazyLock<Mutex<HashMap<PathBuf, QuerySource>>> =
+    LazyLock::new(Mutex::default);
+
 /// Returns a new [`Query`] for a given language and kind
 ///
 /// If the [`Query`] in question does not exist, returns an emtpy
@@ -202,7 +534,7 @@ fn query_from_path(name: &str, kind: &str, language: &Language) -> Result<&'stat
     static QUERIES: LazyLock<Mutex<HashMap<PathBuf, &'static Query>>> =
         LazyLock::new(Mutex::default);
 
-    let queries_dir = duat_core::utils::plugin_dir("duat-treesitter")?.join("queries");
+    let queries_dir = queries_dir()?;
 
     let path = queries_dir.join(name).join(kind).with_extension("scm");
 
@@ -224,6 +556,8 @@ fn query_from_path(name: &str, kind: &str, language: &Language) -> Result<&'stat
             return Ok(query);
         };
 
+        let mut files: Vec<(usize, &'static str)> = vec![(0, name.to_string().leak())];
+
         if let Some(langs) = first_line.strip_prefix("; inherits: ") {
             for name in langs.split(',') {
                 let path = queries_dir.join(name).join(kind).with_extension("scm");
@@ -234,23 +568,58 @@ fn query_from_path(name: &str, kind: &str, language: &Language) -> Result<&'stat
                         }
 
                         query = format!("{inherited_query}\n{query}");
+                        for (start, _) in files.iter_mut() {
+                            *start += inherited_query.len() + 1;
+                        }
+                        files.insert(0, (0, name.to_string().leak()));
                     }
                     Err(err) => context::error!("{err}"),
                 }
             }
         }
 
-        let query = Box::leak(Box::new(match Query::new(language, &query) {
+        let text = query;
+        let query = Box::leak(Box::new(match Query::new(language, &text) {
             Ok(query) => query,
             Err(err) => return Err(txt!("{err}")),
         }));
 
+        let source = QuerySource { text, files };
+        QUERY_SOURCES.lock().unwrap().insert(path.clone(), source);
         queries.insert(path, query);
 
         query
     })
 }
 
+/// The file and line where a pattern of a [`Query`] starts
+///
+/// The file is given by its language, which may be one that `lang`
+/// inherits from. Lines start at 1.
+pub(crate) fn pattern_source(
+    lang: &str,
+    kind: &str,
+    query: &Query,
+    pattern_index: usize,
+) -> Option<(&'static str, usize)> {
+    let path = queries_dir()
+        .ok()?
+        .join(lang)
+        .join(kind)
+        .with_extension("scm");
+    let sources = QUERY_SOURCES.lock().unwrap();
+    let source = sources.get(&path)?;
+
+    let byte = query.start_byte_for_pattern(pattern_index);
+    let i = source
+        .files
+        .partition_point(|(start, _)| *start <= byte)
+        .checked_sub(1)?;
+    let (start, lang) = source.files[i];
+
+    Some((lang, source.text[start..byte].matches('\n').count() + 1))
+}
+
 /// Convenience methods for use of tree-sitter in [`Buffer`]s
 pub trait TsHandle {
     fn get_ts_parser<'p>(&'p self, pa: &'p mut Pass) -> Option<(&'p Parser, &'p Buffer)>;
@@ -270,6 +639,259 @@ pub trait TsHandle {
         pa: &mut Pass,
         selections: impl RangeBounds<usize> + Clone,
     ) -> Option<Vec<usize>>;
+
+    /// The [`Fold`]s of the [`Buffer`]
+    ///
+    /// These come from the `folds.scm` query of the [filetype], as
+    /// well as those of injected languages, and are only calculated
+    /// for the parts of the `Buffer` that have been printed.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    ///
+    /// [filetype]: duat_filetype::FileType::filetype
+    fn ts_folds(&self, pa: &mut Pass) -> Option<Vec<Fold>>;
+
+    /// Opens or closes the [`Fold`]s under the selections, from the
+    /// `start`th selection, to the `end`th selection
+    ///
+    /// Returns the number of `Fold`s that were changed, or [`None`]
+    /// if tree-sitter isn't enabled for the current buffer.
+    fn ts_set_folds(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        action: FoldAction,
+    ) -> Option<usize>;
+
+    /// Opens or closes every [`Fold`] in the [`Buffer`]
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    fn ts_set_all_folds(&self, pa: &mut Pass, is_closed: bool) -> Option<()>;
+
+    /// The [`Locals`] of the tree under a given byte
+    ///
+    /// With these, you can find the [`Definition`] of the symbol on
+    /// that byte, as well as every reference to it in its scope.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer, or if the byte hasn't been parsed yet.
+    fn ts_locals(&self, pa: &mut Pass, byte: usize) -> Option<Locals>;
+
+    /// Enables or disables semantic highlighting
+    ///
+    /// When enabled, references that resolve to a [`Definition`]
+    /// through the `locals.scm` query will be highlighted with the
+    /// form of that `Definition`, like `variable.parameter` or
+    /// `function`.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    fn ts_set_semantic_highlighting(&self, pa: &mut Pass, enabled: bool) -> Option<()>;
+
+    /// The `highlights.scm` captures on a given byte
+    ///
+    /// These include the captures of every injection layer, as well
+    /// as semantic forms, and can be used in order to figure out
+    /// where the form of some text came from. They are ordered by
+    /// priority, so the last one is the form on screen.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    fn ts_highlight_captures(&self, pa: &mut Pass, byte: usize) -> Option<Vec<HighlightCapture>>;
+
+    /// Runs a tree-sitter query on a range of the [`Buffer`]
+    ///
+    /// The query is run on the trees of every language in the
+    /// `Buffer`, including injections, and the returned [`Capture`]s
+    /// tell which language they came from. Layers whose language
+    /// doesn't have the nodes of the query are skipped. Like with
+    /// highlighting, injections are only parsed on the parts of the
+    /// `Buffer` that have been printed.
+    ///
+    /// The most recently used queries are cached, so calling this
+    /// function often with the same query is cheap.
+    ///
+    /// Returns an [`Err`] if tree-sitter isn't enabled for the
+    /// current buffer, or if the query didn't compile for any of its
+    /// languages.
+    fn ts_query(
+        &self,
+        pa: &mut Pass,
+        query: &str,
+        range: impl RangeBounds<usize>,
+    ) -> Result<Vec<Capture>, Text>;
+
+    /// Replaces every match of a query within a range with a
+    /// template
+    ///
+    /// The replaced range of each match is that of its `@match`
+    /// capture or, if there is none, the range spanning all of its
+    /// captures. In the template, `@name` or `@{name}` are replaced
+    /// by the text of the `name` capture, and `@@` is replaced by a
+    /// literal `@`.
+    ///
+    /// All replacements are done as a single change, which can be
+    /// undone at once. Returns the number of replacements, or an
+    /// [`Err`] for the same reasons as [`ts_query`].
+    ///
+    /// [`ts_query`]: TsHandle::ts_query
+    fn ts_replace(
+        &self,
+        pa: &mut Pass,
+        query: &str,
+        template: &str,
+        range: impl RangeBounds<usize>,
+    ) -> Result<usize, Text>;
+
+    /// The text objects with a given name around the selections,
+    /// from the `start`th selection, to the `end`th selection
+    ///
+    /// Text objects come from the `textobjects.scm` query of the
+    /// [filetype], and have names like `function.outer`,
+    /// `class.inner` or `parameter.outer`. For each selection, the
+    /// smallest object that contains it is returned, or [`None`] if
+    /// there is no such object.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    ///
+    /// [filetype]: duat_filetype::FileType::filetype
+    fn ts_textobjects(
+        &self,
+        pa: &mut Pass,
+        name: &str,
+        selections: impl RangeBounds<usize> + Clone,
+    ) -> Option<Vec<Option<Range<usize>>>>;
+
+    /// Expands the selections to the smallest named node around
+    /// them, from the `start`th selection, to the `end`th selection
+    ///
+    /// This works across injected languages, so expanding past the
+    /// root of a code block in Markdown will select the Markdown
+    /// nodes around it. Each expansion is recorded, so it can be
+    /// undone with [`ts_shrink_selections`].
+    ///
+    /// Returns the number of selections that were expanded, or
+    /// [`None`] if tree-sitter isn't enabled for the current buffer.
+    ///
+    /// [`ts_shrink_selections`]: TsHandle::ts_shrink_selections
+    fn ts_expand_selections(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+    ) -> Option<usize>;
+
+    /// Shrinks the selections back to what they were before the
+    /// last [`ts_expand_selections`], from the `start`th selection,
+    /// to the `end`th selection
+    ///
+    /// Selections that were moved since their last expansion are
+    /// left as is.
+    ///
+    /// Returns the number of selections that were shrunk, or
+    /// [`None`] if tree-sitter isn't enabled for the current buffer.
+    ///
+    /// [`ts_expand_selections`]: TsHandle::ts_expand_selections
+    fn ts_shrink_selections(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+    ) -> Option<usize>;
+
+    /// Selects the node reached through a [`NodeMotion`], from the
+    /// `start`th selection, to the `end`th selection
+    ///
+    /// The motion starts from the smallest named node that contains
+    /// each selection.
+    ///
+    /// Returns the number of selections that were moved, or [`None`]
+    /// if tree-sitter isn't enabled for the current buffer.
+    fn ts_move_to_node(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        motion: NodeMotion,
+    ) -> Option<usize>;
+
+    /// Selects the next or previous text object with a given name,
+    /// from the `start`th selection, to the `end`th selection
+    ///
+    /// These come from the `textobjects.scm` query of the
+    /// [filetype], so, in order to jump to the next function, for
+    /// example, the name would be `function.outer`.
+    ///
+    /// Returns the number of selections that were moved, or [`None`]
+    /// if tree-sitter isn't enabled for the current buffer.
+    ///
+    /// [filetype]: duat_filetype::FileType::filetype
+    fn ts_jump_to_textobject(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        name: &str,
+        forward: bool,
+    ) -> Option<usize>;
+
+    /// Swaps the named node under each selection with its next or
+    /// previous named sibling, from the `start`th selection, to the
+    /// `end`th selection
+    ///
+    /// The text between the two nodes, like separators and
+    /// whitespace, is kept, and each selection follows its node.
+    ///
+    /// Returns the number of nodes that were swapped, or [`None`] if
+    /// tree-sitter isn't enabled for the current buffer.
+    fn ts_swap_nodes(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        forward: bool,
+    ) -> Option<usize>;
+
+    /// The byte ranges of the scopes around a byte, outermost first
+    ///
+    /// These come from the `context.scm` query of the [filetype], or
+    /// from its `folds.scm` query, if there is no `context.scm`.
+    /// Scopes of injected languages are included.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    ///
+    /// [filetype]: duat_filetype::FileType::filetype
+    fn ts_context(&self, pa: &mut Pass, byte: usize) -> Option<Vec<Range<usize>>>;
+
+    /// The [`Symbol`]s defined in the [`Buffer`], sorted by position
+    ///
+    /// These come from the `tags.scm` query of the [filetype], or
+    /// from the definitions in its `locals.scm` query, if there is no
+    /// `tags.scm`. Symbols of injected languages are included.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    ///
+    /// [filetype]: duat_filetype::FileType::filetype
+    fn ts_symbols(&self, pa: &mut Pass) -> Option<Vec<Symbol>>;
+
+    /// Parses the whole [`Buffer`], including every injected language
+    ///
+    /// Normally, only the printed lines of a `Buffer` are kept
+    /// parsed, so the trees of `Buffer`s that aren't shown may be
+    /// outdated or missing. After a full parse, every tree is up to
+    /// date, so calls like [`TsHandle::ts_symbols`] or
+    /// [`TsHandle::ts_get_indentations`] see the whole document.
+    ///
+    /// If `in_background` is `true`, the trees are parsed on a
+    /// separate thread, and this returns right away. Either way, the
+    /// [`FullyParsed`] hook is triggered once parsing is done.
+    ///
+    /// Large buffers are only parsed as far as their
+    /// [`LargeFileMode`] allows.
+    ///
+    /// Returns [`None`] if tree-sitter isn't enabled for the current
+    /// buffer.
+    fn ts_parse_full(&self, pa: &mut Pass, in_background: bool) -> Option<()>;
 }
 
 impl TsHandle for Handle {
@@ -303,73 +925,236 @@ impl TsHandle for Handle {
             })
             .collect()
     }
-}
 
-#[allow(unused)]
-fn format_root(node: Node) -> Text {
-    fn format_range(node: Node, builder: &mut Builder) {
-        let mut first = true;
-        for point in [node.start_position(), node.end_position()] {
-            builder.push(txt!(
-                "[punctuation.bracket.TreeView][[[coords.TreeView]{}\
-             	 [punctuation.delimiter.TreeView],[] [coords.TreeView]{}\
-             	 [punctuation.bracket.TreeView]]]",
-                point.row,
-                point.column
-            ));
-
-            if first {
-                first = false;
-                builder.push(txt!("[punctuation.delimiter],[] "));
-            }
-        }
-        builder.push("\n");
+    fn ts_folds(&self, pa: &mut Pass) -> Option<Vec<Fold>> {
+        let (parser, _) = parser::sync_parse(pa, self)?;
+        Some(parser.folds().cloned().collect())
     }
 
-    fn format_node(
-        node: Node,
-        depth: usize,
-        pars: usize,
-        builder: &mut Builder,
-        name: Option<&str>,
-    ) {
-        builder.push("  ".repeat(depth));
+    fn ts_set_folds(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        action: FoldAction,
+    ) -> Option<usize> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
 
-        if let Some(name) = name {
-            builder.push(txt!("[node.field]{name}[punctuation.delimiter.TreeView]: "));
-        }
+        let mut lines: Vec<usize> = self
+            .selections(pa)
+            .iter()
+            .enumerate()
+            .take(range.end)
+            .skip(range.start)
+            .map(|(_, (sel, _))| sel.caret().line())
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
 
-        builder.push(txt!("[punctuation.bracket.TreeView]("));
-        builder.push(txt!("[node.name]{}", node.grammar_name()));
-
-        let mut cursor = node.walk();
-        let named_children = node.named_children(&mut cursor);
-        let len = named_children.len();
-
-        if len == 0 {
-            builder.push(txt!(
-                "[punctuation.bracket.TreeView]{}[] ",
-                ")".repeat(pars)
-            ));
-            format_range(node, builder);
-        } else {
-            builder.push(" ");
-            format_range(node, builder);
-
-            let mut i = 0;
-
-            for (i, child) in named_children.enumerate() {
-                let name = node.field_name_for_named_child(i as u32);
-                let pars = if i == len - 1 { pars + 1 } else { 1 };
-                format_node(child, depth + 1, pars, builder, name);
-            }
-        }
+        let (parser, mut parts) = parser::sync_parse_mut(pa, self)?;
+        Some(parser.set_folds_on(lines, action, &mut parts))
+    }
+
+    fn ts_set_all_folds(&self, pa: &mut Pass, is_closed: bool) -> Option<()> {
+        let (parser, mut parts) = parser::sync_parse_mut(pa, self)?;
+        parser.set_all_folds(is_closed, &mut parts);
+        Some(())
+    }
+
+    fn ts_locals(&self, pa: &mut Pass, byte: usize) -> Option<Locals> {
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        parser.locals(byte, buffer.bytes())
+    }
+
+    fn ts_set_semantic_highlighting(&self, pa: &mut Pass, enabled: bool) -> Option<()> {
+        let (parser, mut parts) = parser::sync_parse_mut(pa, self)?;
+        parser.set_semantic_highlighting(enabled, &mut parts);
+        Some(())
+    }
+
+    fn ts_highlight_captures(&self, pa: &mut Pass, byte: usize) -> Option<Vec<HighlightCapture>> {
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        Some(parser.highlight_captures_on(byte, buffer.bytes()))
+    }
+
+    fn ts_query(
+        &self,
+        pa: &mut Pass,
+        query: &str,
+        range: impl RangeBounds<usize>,
+    ) -> Result<Vec<Capture>, Text> {
+        let Some((parser, buffer)) = parser::sync_parse(pa, self) else {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        };
+
+        let bytes = buffer.bytes();
+        let range = duat_core::utils::get_range(range, bytes.len().byte());
+        parser.query(query, range, bytes)
+    }
+
+    fn ts_replace(
+        &self,
+        pa: &mut Pass,
+        query: &str,
+        template: &str,
+        range: impl RangeBounds<usize>,
+    ) -> Result<usize, Text> {
+        let Some((parser, buffer)) = parser::sync_parse(pa, self) else {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        };
+
+        let bytes = buffer.bytes();
+        let range = duat_core::utils::get_range(range, bytes.len().byte());
+        let matches = parser.query_matches(query, range.clone(), bytes)?;
+        let replacements = replace::replacements(matches, template, range, bytes);
+
+        Ok(replace::apply_replacements(self, pa, replacements))
+    }
+
+    fn ts_textobjects(
+        &self,
+        pa: &mut Pass,
+        name: &str,
+        selections: impl RangeBounds<usize> + Clone,
+    ) -> Option<Vec<Option<Range<usize>>>> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
+
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        let bytes = buffer.bytes();
+
+        Some(
+            selection_ranges(buffer.selections(), range, bytes)
+                .into_iter()
+                .map(|(_, range)| parser.textobject_on(name, range, bytes))
+                .collect(),
+        )
+    }
+
+    fn ts_expand_selections(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+    ) -> Option<usize> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
+
+        let (parser, parts) = parser::sync_parse_mut(pa, self)?;
+        let ranges = selection_ranges(parts.selections, range, parts.bytes);
+        let moves = parser.expand_selections(ranges);
+
+        Some(selections::move_selections(self, pa, moves))
+    }
+
+    fn ts_shrink_selections(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+    ) -> Option<usize> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
+
+        let (parser, parts) = parser::sync_parse_mut(pa, self)?;
+        let ranges = selection_ranges(parts.selections, range, parts.bytes);
+        let moves = parser.shrink_selections(ranges);
+
+        Some(selections::move_selections(self, pa, moves))
+    }
+
+    fn ts_move_to_node(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        motion: NodeMotion,
+    ) -> Option<usize> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
+
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        let moves: Vec<(usize, Range<usize>)> =
+            selection_ranges(buffer.selections(), range, buffer.bytes())
+                .into_iter()
+                .filter_map(|(n, range)| Some((n, parser.node_motion(range, motion)?)))
+                .collect();
+
+        Some(selections::move_selections(self, pa, moves))
+    }
+
+    fn ts_swap_nodes(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        forward: bool,
+    ) -> Option<usize> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
+
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        let bytes = buffer.bytes();
+        let swaps: Vec<_> = selection_ranges(buffer.selections(), range, bytes)
+            .into_iter()
+            .filter_map(|(n, range)| {
+                let (node, sibling) = parser.node_and_sibling(range, forward)?;
+                if node.is_empty() || sibling.is_empty() {
+                    return None;
+                }
+
+                let node_text = bytes[node.clone()].to_string();
+                let sibling_text = bytes[sibling.clone()].to_string();
+                Some((n, Swap { node, node_text, sibling, sibling_text }))
+            })
+            .collect();
+
+        Some(motions::swap_nodes(self, pa, swaps))
+    }
+
+    fn ts_jump_to_textobject(
+        &self,
+        pa: &mut Pass,
+        selections: impl RangeBounds<usize> + Clone,
+        name: &str,
+        forward: bool,
+    ) -> Option<usize> {
+        let range = duat_core::utils::get_range(selections, self.selections(pa).len());
+
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        let bytes = buffer.bytes();
+        let moves: Vec<(usize, Range<usize>)> = selection_ranges(buffer.selections(), range, bytes)
+            .into_iter()
+            .filter_map(|(n, range)| {
+                let object = if forward {
+                    parser.next_textobject(name, range.start, bytes)
+                } else {
+                    parser.prev_textobject(name, range.start, bytes)
+                };
+                Some((n, object?))
+            })
+            .collect();
+
+        Some(selections::move_selections(self, pa, moves))
     }
 
-    let mut cursor = node.walk();
-    let mut builder = Text::builder();
+    fn ts_context(&self, pa: &mut Pass, byte: usize) -> Option<Vec<Range<usize>>> {
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        Some(parser.context_on(byte, buffer.bytes()))
+    }
 
-    format_node(node, 0, 1, &mut builder, None);
+    fn ts_symbols(&self, pa: &mut Pass) -> Option<Vec<Symbol>> {
+        let (parser, buffer) = parser::sync_parse(pa, self)?;
+        Some(parser.symbols(buffer.bytes()))
+    }
+
+    fn ts_parse_full(&self, pa: &mut Pass, in_background: bool) -> Option<()> {
+        parser::parse_full(pa, self, in_background)
+    }
+}
 
-    builder.build()
+/// The byte [`Range`]s of the selections within a `Range` of
+/// indices
+fn selection_ranges(
+    selections: &Selections,
+    range: Range<usize>,
+    bytes: &Bytes,
+) -> Vec<(usize, Range<usize>)> {
+    selections
+        .iter()
+        .enumerate()
+        .take(range.end)
+        .skip(range.start)
+        .map(|(n, (sel, _))| (n, sel.byte_range(bytes)))
+        .collect()
 }
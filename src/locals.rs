@@ -0,0 +1,643 @@
+use std::{collections::HashMap, ops::Range};
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context,
+    data::Pass,
+    form::FormId,
+    text::{Bytes, txt},
+};
+use tree_sitter::{Node, Query, QueryCursor, StreamingIterator};
+
+use crate::{TsHandle, parser::TsBuf, predicates::satisfies_predicates};
+
+/// Adds the command used for toggling semantic highlighting
+pub(crate) fn add_semantic_command() {
+    cmd::add(
+        "semantic-highlighting-toggle",
+        |pa: &mut Pass| -> CmdResult {
+            let handle = context::current_buffer(pa);
+            let Some(enabled) = handle
+                .get_ts_parser(pa)
+                .map(|(parser, _)| parser.semantic_highlighting())
+            else {
+                return Err(txt!("Tree-sitter is not enabled for this buffer"));
+            };
+
+            handle.ts_set_semantic_highlighting(pa, !enabled);
+            Ok(None)
+        },
+    )
+    .doc(
+        txt!("Toggles highlighting references with the form of their definitions"),
+        None,
+    );
+}
+
+/// The scopes, definitions and references of a tree
+///
+/// These are taken from the `locals.scm` query of a language, and
+/// can be used in order to figure out where a local symbol was
+/// defined, as well as where it is referenced.
+#[derive(Debug, Clone)]
+pub struct Locals {
+    lang: &'static str,
+    scopes: Vec<Scope>,
+    definitions: Vec<Definition>,
+    references: Vec<Reference>,
+}
+
+impl Locals {
+    /// Returns a new `Locals`, from a `locals.scm` [`Query`]
+    pub(crate) fn new(
+        lang: &'static str,
+        locals: &'static Query,
+        root: Node,
+        bytes: &Bytes,
+    ) -> Self {
+        let names = locals.capture_names();
+
+        let mut scope_ranges = vec![root.byte_range()];
+        let mut def_parts = Vec::new();
+        let mut ref_parts = Vec::new();
+
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(locals, root, TsBuf(bytes));
+
+        while let Some(qm) = matches.next() {
+            if !satisfies_predicates(locals, qm, None) {
+                continue;
+            }
+
+            let props = locals.property_settings(qm.pattern_index);
+
+            for cap in qm.captures.iter() {
+                let range = cap.node.byte_range();
+                match names[cap.index as usize] {
+                    "local.scope" => scope_ranges.push(range),
+                    "local.reference" => {
+                        let name = bytes[range.clone()].to_string();
+                        ref_parts.push((range, name));
+                    }
+                    name => {
+                        let Some(kind) = name.strip_prefix("local.definition") else {
+                            continue;
+                        };
+                        let kind = kind.strip_prefix('.').unwrap_or(kind);
+
+                        let key = format!("definition.{kind}.scope");
+                        let target = props
+                            .iter()
+                            .find(|p| *p.key == key)
+                            .and_then(|p| p.value.as_deref())
+                            .map(ScopeTarget::from_value)
+                            .unwrap_or(ScopeTarget::Own);
+
+                        let name = bytes[range.clone()].to_string();
+                        let initializer = initializer_of(cap.node);
+                        def_parts.push(DefParts { range, name, kind, target, initializer });
+                    }
+                }
+            }
+        }
+
+        Self::from_parts(lang, scope_ranges, def_parts, ref_parts)
+    }
+
+    /// Returns a new `Locals`, from the captures of a `locals.scm`
+    /// [`Query`]
+    ///
+    /// Every reference is resolved here, so looking up their
+    /// [`Definition`]s later on is cheap.
+    fn from_parts(
+        lang: &'static str,
+        mut scope_ranges: Vec<Range<usize>>,
+        def_parts: Vec<DefParts>,
+        ref_parts: Vec<(Range<usize>, String)>,
+    ) -> Self {
+        scope_ranges
+            .sort_unstable_by(|lhs, rhs| lhs.start.cmp(&rhs.start).then(rhs.end.cmp(&lhs.end)));
+        scope_ranges.dedup();
+
+        // Since the ranges are sorted by start, then by reverse end, a
+        // scope's parent is the last one in the stack that contains it.
+        let mut scopes: Vec<Scope> = Vec::new();
+        let mut stack: Vec<usize> = Vec::new();
+        for range in scope_ranges {
+            while let Some(&i) = stack.last()
+                && scopes[i].range.end < range.end
+            {
+                stack.pop();
+            }
+
+            scopes.push(Scope { range, parent: stack.last().copied() });
+            stack.push(scopes.len() - 1);
+        }
+
+        let mut locals = Self {
+            lang,
+            scopes,
+            definitions: Vec::new(),
+            references: Vec::new(),
+        };
+
+        for DefParts { range, name, kind, target, initializer } in def_parts {
+            // Scopes like match arms bind names for their own values.
+            let initializer = initializer
+                .filter(|(owner, _)| !locals.is_scope(owner))
+                .map(|(_, value)| value);
+            let scope = locals.scope_on(range.start);
+            let scope = match target {
+                ScopeTarget::Own => scope,
+                ScopeTarget::Parent => locals.scopes[scope].parent.unwrap_or(scope),
+                ScopeTarget::Global => 0,
+            };
+
+            locals
+                .definitions
+                .push(Definition { range, name, kind, scope, initializer });
+        }
+
+        locals
+            .definitions
+            .sort_unstable_by_key(|item| item.range.start);
+        locals
+            .definitions
+            .dedup_by(|lhs, rhs| lhs.range == rhs.range);
+
+        for (range, name) in ref_parts {
+            let scope = locals.scope_on(range.start);
+            locals
+                .references
+                .push(Reference { range, name, scope, definition: None });
+        }
+
+        locals
+            .references
+            .sort_unstable_by_key(|item| item.range.start);
+        locals
+            .references
+            .dedup_by(|lhs, rhs| lhs.range == rhs.range);
+
+        let mut by_name: HashMap<(usize, &str), Vec<usize>> = HashMap::new();
+        for (i, def) in locals.definitions.iter().enumerate() {
+            by_name.entry((def.scope, &def.name)).or_default().push(i);
+        }
+
+        let definitions: Vec<Option<usize>> = locals
+            .references
+            .iter()
+            .map(|reference| locals.resolve(&by_name, reference))
+            .collect();
+
+        for (reference, definition) in locals.references.iter_mut().zip(definitions) {
+            reference.definition = definition;
+        }
+
+        locals
+    }
+
+    /// The language of the tree that these `Locals` belong to
+    pub fn lang(&self) -> &'static str {
+        self.lang
+    }
+
+    /// An [`Iterator`] over all [`Definition`]s, ordered by position
+    pub fn definitions(&self) -> std::slice::Iter<'_, Definition> {
+        self.definitions.iter()
+    }
+
+    /// The [`Definition`] of the symbol on a given byte
+    ///
+    /// If the byte is on a `Definition`, that one is returned,
+    /// otherwise, the reference on that byte is resolved by looking
+    /// for `Definition`s with the same name in each enclosing scope,
+    /// from the innermost to the outermost.
+    pub fn definition_on(&self, byte: usize) -> Option<&Definition> {
+        if let Some(def) = self
+            .definitions
+            .iter()
+            .find(|def| def.range.contains(&byte))
+        {
+            return Some(def);
+        }
+
+        let reference = self.references.iter().find(|r| r.range.contains(&byte))?;
+
+        Some(&self.definitions[reference.definition?])
+    }
+
+    /// The ranges of all references to a [`Definition`]
+    ///
+    /// This includes the range of the `Definition` itself, and is
+    /// ordered by position.
+    pub fn references_to(&self, def: &Definition) -> Vec<Range<usize>> {
+        let Some(def_i) = self.definitions.iter().position(|d| d == def) else {
+            return Vec::new();
+        };
+
+        let mut ranges: Vec<Range<usize>> = self
+            .references
+            .iter()
+            .filter(|r| r.definition == Some(def_i))
+            .map(|r| r.range.clone())
+            .collect();
+
+        if !ranges.contains(&def.range) {
+            let i = ranges.partition_point(|r| r.start < def.range.start);
+            ranges.insert(i, def.range.clone());
+        }
+
+        ranges
+    }
+
+    /// The byte [`Range`] of the scope of a [`Definition`]
+    pub fn scope_of(&self, def: &Definition) -> Range<usize> {
+        self.scopes[def.scope].range.clone()
+    }
+
+    /// Wether a [`Range`] is that of a local symbol
+    ///
+    /// This is the case for [`Definition`]s, as well as references
+    /// that resolve to one.
+    pub(crate) fn is_local(&self, range: Range<usize>) -> bool {
+        let def_i = self
+            .definitions
+            .partition_point(|def| def.range.start < range.start);
+        let ref_i = self
+            .references
+            .partition_point(|r| r.range.start < range.start);
+
+        self.definitions[def_i..]
+            .iter()
+            .take_while(|def| def.range.start == range.start)
+            .any(|def| def.range == range)
+            || self.references[ref_i..]
+                .iter()
+                .take_while(|r| r.range.start == range.start)
+                .any(|r| r.range == range && r.definition.is_some())
+    }
+
+    /// Wether a [`Range`] is exactly that of a scope
+    fn is_scope(&self, range: &Range<usize>) -> bool {
+        self.scopes
+            .binary_search_by(|scope| {
+                scope
+                    .range
+                    .start
+                    .cmp(&range.start)
+                    .then(range.end.cmp(&scope.range.end))
+            })
+            .is_ok()
+    }
+
+    /// The innermost scope that contains a byte
+    fn scope_on(&self, byte: usize) -> usize {
+        // Scopes are nested, so the innermost one that contains the byte
+        // is the last one that starts before it, or one of its parents.
+        let mut scope = self
+            .scopes
+            .partition_point(|scope| scope.range.start <= byte)
+            .checked_sub(1);
+
+        while let Some(i) = scope {
+            if byte < self.scopes[i].range.end {
+                return i;
+            }
+            scope = self.scopes[i].parent;
+        }
+
+        0
+    }
+
+    /// Resolves a [`Reference`] to the index of a [`Definition`]
+    ///
+    /// In each scope, the last `Definition` visible from the
+    /// reference is preferred, since it is the one that would shadow
+    /// the others. Functions and types are also visible before they
+    /// are defined.
+    fn resolve(
+        &self,
+        by_name: &HashMap<(usize, &str), Vec<usize>>,
+        reference: &Reference,
+    ) -> Option<usize> {
+        let mut scope = Some(reference.scope);
+        let start = reference.range.start;
+
+        while let Some(i) = scope {
+            if let Some(candidates) = by_name.get(&(i, reference.name.as_str())) {
+                let visible = candidates
+                    .partition_point(|&def_i| self.definitions[def_i].range.start <= start);
+
+                let before = candidates[..visible].iter().rev().find(|&&def_i| {
+                    let initializer = &self.definitions[def_i].initializer;
+                    !initializer.as_ref().is_some_and(|r| r.contains(&start))
+                });
+                let hoisted = || {
+                    candidates
+                        .iter()
+                        .find(|&&def_i| self.definitions[def_i].is_hoisted())
+                };
+
+                if let Some(&def_i) = before.or_else(hoisted) {
+                    return Some(def_i);
+                }
+            }
+
+            scope = self.scopes[i].parent;
+        }
+
+        None
+    }
+
+    /// The forms that references take from their [`Definition`]s
+    ///
+    /// The form of a `Definition` is the last `highlights.scm`
+    /// capture that spans exactly its range, like
+    /// `variable.parameter` or `function`.
+    pub(crate) fn semantic_forms(
+        self,
+        highlights: &Query,
+        forms: &[(FormId, u8)],
+        root: Node,
+        bytes: &Bytes,
+    ) -> SemanticForms {
+        let mut def_forms = vec![None; self.definitions.len()];
+
+        let mut cursor = QueryCursor::new();
+        let mut hi_captures = cursor.captures(highlights, root, TsBuf(bytes));
+
+        while let Some((qm, i)) = hi_captures.next() {
+            if !satisfies_predicates(highlights, qm, Some(&self)) {
+                continue;
+            }
+
+            let cap = qm.captures[*i];
+            let range = cap.node.byte_range();
+
+            if let Ok(def_i) = self
+                .definitions
+                .binary_search_by_key(&range.start, |def| def.range.start)
+                && self.definitions[def_i].range == range
+            {
+                def_forms[def_i] = Some(forms[cap.index as usize].0);
+            }
+        }
+
+        let references = self
+            .references
+            .iter()
+            .filter_map(|reference| {
+                let form = def_forms[reference.definition?]?;
+                Some((reference.range.clone(), form))
+            })
+            .collect();
+
+        SemanticForms { locals: self, references }
+    }
+}
+
+/// The forms of references, taken from their [`Definition`]s
+#[derive(Debug)]
+pub(crate) struct SemanticForms {
+    locals: Locals,
+    references: Vec<(Range<usize>, FormId)>,
+}
+
+impl SemanticForms {
+    /// The [`Locals`] that these forms came from
+    pub fn locals(&self) -> &Locals {
+        &self.locals
+    }
+
+    /// The references that intersect a [`Range`], with their forms
+    pub fn forms_on(&self, range: Range<usize>) -> impl Iterator<Item = (Range<usize>, FormId)> {
+        let start = self
+            .references
+            .partition_point(|(r, _)| r.end <= range.start);
+
+        self.references[start..]
+            .iter()
+            .take_while(move |(r, _)| r.start < range.end)
+            .cloned()
+    }
+
+    /// The scopes whose references may have changed from an edit
+    ///
+    /// These are the innermost scope around the edit, as well as the
+    /// scopes of every [`Definition`] within it.
+    pub fn scopes_affected_by(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let locals = &self.locals;
+        let innermost = locals.scope_on(range.start);
+
+        let mut scopes = vec![locals.scopes[innermost].range.clone()];
+        scopes.extend(
+            locals
+                .definitions
+                .iter()
+                .filter(|def| def.range.start < range.end && range.start < def.range.end)
+                .map(|def| locals.scopes[def.scope].range.clone()),
+        );
+
+        scopes
+    }
+}
+
+/// A local definition, from a `@local.definition.*` capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    range: Range<usize>,
+    name: String,
+    kind: &'static str,
+    scope: usize,
+    initializer: Option<Range<usize>>,
+}
+
+impl Definition {
+    /// The byte [`Range`] of the defined name
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// The defined name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The kind of definition, like `"var"` or `"function"`
+    ///
+    /// This is the suffix of the `@local.definition.*` capture, and
+    /// is empty for plain `@local.definition` captures.
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    /// Wether this `Definition` is visible before its position
+    fn is_hoisted(&self) -> bool {
+        let kind = self.kind.split('.').next().unwrap_or("");
+        matches!(kind, "function" | "method" | "type")
+    }
+}
+
+/// The initializer that a defined [`Node`] is bound to
+///
+/// This is the `value` or `right` field of the closest ancestor that
+/// has one, like the `x + 1` in `let x = x + 1`, returned alongside
+/// the range of said ancestor. The defined name is not visible from
+/// within it.
+fn initializer_of(node: Node) -> Option<(Range<usize>, Range<usize>)> {
+    let mut ancestor = node.parent();
+
+    while let Some(parent) = ancestor {
+        if let Some(value) = parent
+            .child_by_field_name("value")
+            .or_else(|| parent.child_by_field_name("right"))
+        {
+            return (node.end_byte() <= value.start_byte())
+                .then(|| (parent.byte_range(), value.byte_range()));
+        }
+        ancestor = parent.parent();
+    }
+
+    None
+}
+
+/// A lexical scope, from a `@local.scope` capture
+#[derive(Debug, Clone)]
+struct Scope {
+    range: Range<usize>,
+    parent: Option<usize>,
+}
+
+/// A reference to a symbol, from a `@local.reference` capture
+#[derive(Debug, Clone)]
+struct Reference {
+    range: Range<usize>,
+    name: String,
+    scope: usize,
+    definition: Option<usize>,
+}
+
+/// The parts of a [`Definition`], before it is placed in a scope
+struct DefParts {
+    range: Range<usize>,
+    name: String,
+    kind: &'static str,
+    target: ScopeTarget,
+    initializer: Option<(Range<usize>, Range<usize>)>,
+}
+
+/// Where a [`Definition`] should be placed, from a
+/// `definition.*.scope` property
+#[derive(Clone, Copy)]
+enum ScopeTarget {
+    Own,
+    Parent,
+    Global,
+}
+
+impl ScopeTarget {
+    fn from_value(value: &str) -> Self {
+        match value {
+            "parent" => Self::Parent,
+            "global" => Self::Global,
+            _ => Self::Own,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(range: Range<usize>, name: &str, kind: &'static str) -> DefParts {
+        DefParts {
+            range,
+            name: name.to_string(),
+            kind,
+            target: ScopeTarget::Own,
+            initializer: None,
+        }
+    }
+
+    fn init(def: DefParts, owner: Range<usize>, value: Range<usize>) -> DefParts {
+        DefParts { initializer: Some((owner, value)), ..def }
+    }
+
+    /// `Locals` for a 100 byte long root, with an optional inner
+    /// scope
+    fn locals(
+        scope: Option<Range<usize>>,
+        defs: Vec<DefParts>,
+        refs: &[(Range<usize>, &str)],
+    ) -> Locals {
+        let refs = refs
+            .iter()
+            .map(|(range, name)| (range.clone(), name.to_string()))
+            .collect();
+        let mut scopes = Vec::new();
+        scopes.push(0..100);
+        scopes.extend(scope);
+        Locals::from_parts("test", scopes, defs, refs)
+    }
+
+    fn def_of(locals: &Locals, byte: usize) -> Option<Range<usize>> {
+        locals.definition_on(byte).map(Definition::range)
+    }
+
+    #[test]
+    fn later_definitions_shadow_earlier_ones() {
+        // x = 1; { y(x); x = 2; x = x + 1; y(x) }
+        let locals = locals(
+            Some(10..90),
+            vec![
+                def(2..3, "x", "var"),
+                init(def(20..21, "x", "var"), 16..26, 24..25),
+                init(def(30..31, "x", "var"), 26..40, 34..39),
+            ],
+            &[(15..16, "x"), (34..35, "x"), (50..51, "x")],
+        );
+
+        assert_eq!(def_of(&locals, 15), Some(2..3));
+        assert_eq!(def_of(&locals, 34), Some(20..21));
+        assert_eq!(def_of(&locals, 50), Some(30..31));
+        assert_eq!(locals.references_to(locals.definition_on(30).unwrap()), [
+            30..31,
+            50..51
+        ]);
+    }
+
+    #[test]
+    fn only_functions_and_types_are_hoisted() {
+        // f(y); fn f() {}; y = 1
+        let locals = locals(
+            None,
+            vec![def(60..61, "f", "function"), def(70..71, "y", "var")],
+            &[(5..6, "f"), (7..8, "y")],
+        );
+
+        assert_eq!(def_of(&locals, 5), Some(60..61));
+        assert_eq!(def_of(&locals, 7), None);
+        assert!(locals.is_local(5..6));
+        assert!(!locals.is_local(7..8));
+        assert!(locals.is_local(70..71));
+    }
+
+    #[test]
+    fn names_are_not_visible_in_their_own_initializers() {
+        // let x = x; match v { y => y }
+        let locals = locals(
+            Some(20..40),
+            vec![
+                init(def(4..5, "x", "var"), 0..10, 8..9),
+                init(def(22..23, "y", "var"), 20..40, 26..39),
+            ],
+            &[(8..9, "x"), (30..31, "y")],
+        );
+
+        assert_eq!(def_of(&locals, 8), None);
+        assert!(!locals.is_local(8..9));
+        // Match arms are scopes, so their patterns bind into the value.
+        assert_eq!(def_of(&locals, 30), Some(22..23));
+    }
+}
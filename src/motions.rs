@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    text::txt,
+};
+
+use crate::TsHandle;
+
+/// Adds the commands used for moving through the syntax tree
+pub(crate) fn add_motion_commands() {
+    fn move_to_node(pa: &mut Pass, motion: NodeMotion) -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_move_to_node(pa, .., motion) {
+            Some(0) => Err(txt!("No nodes to move to")),
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    }
+
+    fn jump_to_textobject(pa: &mut Pass, name: &str, forward: bool) -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_jump_to_textobject(pa, .., name, forward) {
+            Some(0) => Err(txt!("No [a]{name}[] to jump to")),
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    }
+
+    fn swap_nodes(pa: &mut Pass, forward: bool) -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_swap_nodes(pa, .., forward) {
+            Some(0) => Err(txt!("No siblings to swap with")),
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    }
+
+    cmd::add("node-next", |pa: &mut Pass| {
+        move_to_node(pa, NodeMotion::NextSibling)
+    })
+    .doc(txt!("Selects the next named sibling of each node"), None);
+    cmd::add("node-prev", |pa: &mut Pass| {
+        move_to_node(pa, NodeMotion::PrevSibling)
+    })
+    .doc(
+        txt!("Selects the previous named sibling of each node"),
+        None,
+    );
+    cmd::add("node-parent", |pa: &mut Pass| {
+        move_to_node(pa, NodeMotion::Parent)
+    })
+    .doc(txt!("Selects the parent of each node"), None);
+    cmd::add("node-child", |pa: &mut Pass| {
+        move_to_node(pa, NodeMotion::FirstChild)
+    })
+    .doc(txt!("Selects the first named child of each node"), None);
+    cmd::add("node-swap-next", |pa: &mut Pass| swap_nodes(pa, true))
+        .doc(txt!("Swaps each node with its next named sibling"), None);
+    cmd::add("node-swap-prev", |pa: &mut Pass| swap_nodes(pa, false)).doc(
+        txt!("Swaps each node with its previous named sibling"),
+        None,
+    );
+
+    cmd::add("function-next", |pa: &mut Pass| {
+        jump_to_textobject(pa, "function.outer", true)
+    })
+    .doc(txt!("Selects the next function"), None);
+    cmd::add("function-prev", |pa: &mut Pass| {
+        jump_to_textobject(pa, "function.outer", false)
+    })
+    .doc(txt!("Selects the previous function"), None);
+    cmd::add("class-next", |pa: &mut Pass| {
+        jump_to_textobject(pa, "class.outer", true)
+    })
+    .doc(txt!("Selects the next class"), None);
+    cmd::add("class-prev", |pa: &mut Pass| {
+        jump_to_textobject(pa, "class.outer", false)
+    })
+    .doc(txt!("Selects the previous class"), None);
+}
+
+/// A motion from the node under a selection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeMotion {
+    /// Moves to the next named sibling
+    NextSibling,
+    /// Moves to the previous named sibling
+    PrevSibling,
+    /// Moves to the closest named ancestor
+    Parent,
+    /// Moves to the first named child
+    FirstChild,
+}
+
+/// Swaps the `n`th nodes with their siblings
+///
+/// Since the text between the nodes is kept, the swaps don't change
+/// the positions of other selections. Each selection is moved to
+/// follow its node.
+///
+/// Returns the number of nodes that were swapped.
+pub(crate) fn swap_nodes(handle: &Handle, pa: &mut Pass, swaps: Vec<(usize, Swap)>) -> usize {
+    let swaps = dedup_swaps(swaps);
+
+    let mut n = 0;
+    handle.edit_all(pa, |mut c| {
+        if let Some((_, swap, edits)) = swaps.iter().find(|(i, ..)| *i == n) {
+            let Swap { node, node_text, sibling, sibling_text } = swap;
+            // The later range is replaced first, so the earlier one stays valid.
+            let [(first, first_text), (second, second_text)] = if node.start < sibling.start {
+                [(node, node_text), (sibling, sibling_text)]
+            } else {
+                [(sibling, sibling_text), (node, node_text)]
+            };
+
+            if *edits {
+                c.move_to(second.clone());
+                c.replace(first_text);
+                c.move_to(first.clone());
+                c.replace(second_text);
+            }
+
+            if node.start < sibling.start {
+                c.move_to(second.end - node.len()..second.end);
+            } else {
+                c.move_to(first.start..first.start + node.len());
+            }
+        }
+        n += 1;
+    });
+
+    swaps.iter().filter(|(.., edits)| *edits).count()
+}
+
+/// Which of the `n`th [`Swap`]s should edit the text
+///
+/// A `Swap` equal to an earlier one doesn't edit the text, but its
+/// selection still follows the node. A `Swap` that overlaps with an
+/// earlier one is dropped, since its text would be outdated.
+fn dedup_swaps(swaps: Vec<(usize, Swap)>) -> Vec<(usize, Swap, bool)> {
+    let mut deduped: Vec<(usize, Swap, bool)> = Vec::new();
+
+    for (n, swap) in swaps {
+        let overlaps = |other: &Swap| {
+            [&other.node, &other.sibling].into_iter().any(|lhs| {
+                [&swap.node, &swap.sibling]
+                    .into_iter()
+                    .any(|rhs| lhs.start < rhs.end && rhs.start < lhs.end)
+            })
+        };
+
+        if deduped
+            .iter()
+            .any(|(_, other, _)| other.node == swap.node && other.sibling == swap.sibling)
+        {
+            deduped.push((n, swap, false));
+        } else if !deduped.iter().any(|(_, other, _)| overlaps(other)) {
+            deduped.push((n, swap, true));
+        }
+    }
+
+    deduped
+}
+
+/// A node and its sibling, along with their texts
+pub(crate) struct Swap {
+    pub node: Range<usize>,
+    pub node_text: String,
+    pub sibling: Range<usize>,
+    pub sibling_text: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap(node: Range<usize>, sibling: Range<usize>) -> Swap {
+        Swap {
+            node_text: "a".repeat(node.len()),
+            node,
+            sibling_text: "b".repeat(sibling.len()),
+            sibling,
+        }
+    }
+
+    fn edits(swaps: Vec<(usize, Swap)>) -> Vec<(usize, bool)> {
+        dedup_swaps(swaps)
+            .into_iter()
+            .map(|(n, _, edits)| (n, edits))
+            .collect()
+    }
+
+    #[test]
+    fn two_carets_in_the_same_node() {
+        let swaps = vec![(0, swap(0..3, 5..8)), (1, swap(0..3, 5..8))];
+        assert_eq!(edits(swaps), [(0, true), (1, false)]);
+    }
+
+    #[test]
+    fn overlapping_swaps_are_dropped() {
+        let swaps = vec![
+            (0, swap(0..3, 5..8)),
+            (1, swap(5..8, 10..13)),
+            (2, swap(15..18, 20..23)),
+        ];
+        assert_eq!(edits(swaps), [(0, true), (2, true)]);
+    }
+}
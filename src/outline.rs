@@ -0,0 +1,261 @@
+use std::{ops::Range, sync::LazyLock};
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    form,
+    mode::{MouseButton, MouseEvent, MouseEventKind},
+    text::{Tagger, Text, TextMut, TextState, TwoPoints, txt},
+    ui::{PushSpecs, Side, Widget},
+};
+
+use crate::{TsHandle, parser};
+
+/// Adds the command used for toggling the [`Outline`]
+pub(crate) fn add_outline_command() {
+    cmd::add("outline", |pa: &mut Pass| -> CmdResult {
+        let handle = context::current_buffer(pa);
+
+        let outline = handle
+            .get_related::<Outline>(pa)
+            .next()
+            .map(|(outline, _)| outline);
+        if let Some(outline) = outline {
+            outline.close(pa)?;
+            return Ok(None);
+        }
+
+        if handle.get_ts_parser(pa).is_none() {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        }
+
+        let specs = PushSpecs {
+            side: Side::Right,
+            width: Some(40.0),
+            ..PushSpecs::default()
+        };
+        handle.push_outer_widget(pa, Outline::new(handle.clone()), specs);
+
+        Ok(None)
+    })
+    .doc(
+        txt!("Toggles an outline of the symbols of the current buffer"),
+        None,
+    );
+}
+
+/// A symbol defined in a [`Buffer`], like a function or a type
+///
+/// These come from the `@definition.{kind}` captures of `tags.scm`,
+/// whose `@name` capture is the name of the symbol. For languages
+/// without that query, the `@local.definition.{kind}` captures of
+/// `locals.scm` are used instead, ignoring variables, parameters,
+/// fields and imports.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub(crate) name: String,
+    pub(crate) kind: &'static str,
+    pub(crate) range: Range<usize>,
+    pub(crate) name_range: Range<usize>,
+    pub(crate) lang: &'static str,
+    pub(crate) depth: usize,
+}
+
+impl Symbol {
+    /// The name of the symbol
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The kind of the symbol, like `"function"` or `"class"`
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    /// The byte [`Range`] of the whole definition
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// The byte [`Range`] of the name of the symbol
+    pub fn name_range(&self) -> Range<usize> {
+        self.name_range.clone()
+    }
+
+    /// The language of the tree that the symbol came from
+    pub fn lang(&self) -> &'static str {
+        self.lang
+    }
+
+    /// How many other symbols this one is nested in
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Sets the depth of each [`Symbol`], sorting and deduplicating them
+///
+/// If many symbols have the same range, only the first one is kept.
+pub(crate) fn nest_symbols(symbols: &mut Vec<Symbol>) {
+    symbols.sort_by(|lhs, rhs| {
+        (lhs.range.start.cmp(&rhs.range.start)).then(rhs.range.end.cmp(&lhs.range.end))
+    });
+    symbols.dedup_by(|rhs, lhs| rhs.range == lhs.range);
+
+    // Since the ranges are sorted by start, then by reverse end, a
+    // symbol's parent is the last one in the stack that contains it.
+    let mut stack: Vec<Range<usize>> = Vec::new();
+    for symbol in symbols.iter_mut() {
+        while let Some(range) = stack.last()
+            && range.end < symbol.range.end
+        {
+            stack.pop();
+        }
+
+        symbol.depth = stack.len();
+        stack.push(symbol.range.clone());
+    }
+}
+
+/// A [`Widget`] that shows the [`Symbol`]s of a [`Buffer`]
+///
+/// Each symbol is indented by how deeply it is nested. The innermost
+/// symbol under the main caret of the `Buffer` is highlighted with
+/// the `selected.Outline` form, and clicking on a symbol selects its
+/// name in the `Buffer`.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+pub struct Outline {
+    text: Text,
+    buffer: Handle,
+    symbols: Vec<Symbol>,
+    text_state: Option<TextState>,
+    selected: Option<usize>,
+}
+
+impl Outline {
+    /// Returns a new `Outline`, for the symbols of a [`Buffer`]
+    ///
+    /// [`Buffer`]: duat_core::buffer::Buffer
+    pub fn new(buffer: Handle) -> Self {
+        Self {
+            text: Text::default(),
+            buffer,
+            symbols: Vec::new(),
+            text_state: None,
+            selected: None,
+        }
+    }
+
+    /// The line of the innermost [`Symbol`] that contains a byte
+    fn line_of(&self, byte: usize) -> Option<usize> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, symbol)| symbol.range.contains(&byte))
+            .map(|(line, _)| line)
+    }
+}
+
+impl Widget for Outline {
+    fn update(pa: &mut Pass, handle: &Handle<Self>) {
+        let buffer = handle.read(pa).buffer.clone();
+        if buffer.is_closed(pa) {
+            _ = handle.close(pa);
+            return;
+        }
+
+        let last_state = handle.read(pa).text_state;
+
+        let Some((parser, buf)) = parser::sync_parse(pa, &buffer) else {
+            return;
+        };
+
+        let text_state = buf.text().text_state();
+        let caret = buf.selections().get_main().map(|sel| sel.caret().byte());
+
+        let rebuilt = (last_state != Some(text_state)).then(|| {
+            let symbols = parser.symbols(buf.bytes());
+            let mut builder = Text::builder();
+
+            for symbol in symbols.iter() {
+                let indent = "  ".repeat(symbol.depth);
+                builder.push(txt!(
+                    "{indent}[symbol.kind.Outline]{}[] [symbol.name.Outline]{}\n",
+                    symbol.kind,
+                    symbol.name
+                ));
+            }
+
+            (builder.build(), symbols)
+        });
+
+        let outline = handle.write(pa);
+        let was_rebuilt = rebuilt.is_some();
+        if let Some((text, symbols)) = rebuilt {
+            outline.text = text;
+            outline.symbols = symbols;
+            outline.text_state = Some(text_state);
+        }
+
+        let selected = caret.and_then(|byte| outline.line_of(byte));
+        if selected != outline.selected || was_rebuilt {
+            let tagger = outline_tagger();
+            outline.text.remove_tags(tagger, ..);
+            outline.selected = selected;
+
+            if let Some(line) = selected {
+                let range = outline.text.bytes().line(line).byte_range();
+                let point = outline.text.bytes().point_at_byte(range.start);
+                let form = form::id_of!("selected.Outline");
+                outline.text.insert_tag(tagger, range, form.to_tag(99));
+
+                handle.scroll_to_points(pa, TwoPoints::new_after_ghost(point));
+            }
+        }
+
+        handle.read(pa).buffer.declare_as_read();
+    }
+
+    fn on_mouse_event(pa: &mut Pass, handle: &Handle<Self>, event: MouseEvent)
+    where
+        Self: Sized,
+    {
+        let (MouseEventKind::Down(MouseButton::Left), Some(points)) = (event.kind, event.points)
+        else {
+            return;
+        };
+
+        let outline = handle.read(pa);
+        let line = points.points().real.line();
+        let Some(range) = outline.symbols.get(line).map(Symbol::name_range) else {
+            return;
+        };
+
+        outline
+            .buffer
+            .clone()
+            .edit_main(pa, |mut c| c.move_to(range));
+    }
+
+    fn needs_update(&self, pa: &Pass) -> bool {
+        self.buffer.has_changed(pa)
+    }
+
+    fn text(&self) -> &Text {
+        &self.text
+    }
+
+    fn text_mut(&mut self) -> TextMut<'_> {
+        self.text.as_mut()
+    }
+}
+
+fn outline_tagger() -> Tagger {
+    static TAGGER: LazyLock<Tagger> = Tagger::new_static();
+    *TAGGER
+}
@@ -1,8 +1,8 @@
 use std::{
     collections::HashMap,
-    ops::{ControlFlow, Range},
-    sync::{LazyLock, Mutex},
-    time::{Duration, Instant},
+    ops::Range,
+    sync::{Arc, LazyLock, Mutex},
+    time::Instant,
 };
 
 use duat_core::{
@@ -11,105 +11,262 @@ use duat_core::{
     context::{self, Handle},
     data::Pass,
     form::{self, FormId},
-    hook::{self, BufferUpdated},
+    hook::{self, BufferUpdated, Hookable},
     opts::PrintOpts,
-    text::{Bytes, Point, Tagger},
+    text::{Bytes, Point, Tagger, Text},
 };
 use duat_filetype::{FileType, PassFileType};
 use tree_sitter::{
-    InputEdit, Node, ParseOptions, ParseState, Parser as TsParser, Point as TsPoint, QueryCapture,
-    QueryCursor, QueryMatch, QueryProperty, Range as TsRange, StreamingIterator, TextProvider,
+    InputEdit, Language, Node, Parser as TsParser, Point as TsPoint, Query, QueryCapture,
+    QueryCursor, QueryMatch, QueryPredicateArg, QueryProperty, Range as TsRange, StreamingIterator,
+    TextProvider, Tree as TsTree,
 };
 
-use crate::{LangParts, Queries, lang_parts_of, query_from_path, tree::Trees};
+use crate::{
+    LangParts, Queries,
+    cursor::Cursor,
+    folds::{Fold, FoldAction, Folds, fold_tagger},
+    inspect::HighlightCapture,
+    lang_parts_of,
+    languages::{lang_from_info_string, lang_from_mimetype},
+    large_file::{self, LargeFileMode, LongLines},
+    locals::{Locals, SemanticForms},
+    motions::NodeMotion,
+    options,
+    outline::{self, Symbol},
+    predicates::{capture_range, capture_text, row_offsets, satisfies_predicates},
+    query::{Capture, cached_query},
+    query_from_path,
+    selections::SelectionHistory,
+    snapshot::Snapshot,
+    tree::Trees,
+    worker::{self, ParsedTree},
+};
 
-const PARSE_TIMEOUT: Duration = Duration::from_millis(3);
+/// The highest priority of a form from [`forms_from_lang_parts`]
+///
+/// This leaves room for the priorities above it, since form tags
+/// can't get too close to [`u8::MAX`].
+const MAX_PRIORITY: usize = 200;
 static TRACKER: BufferTracker = BufferTracker::new();
 static PARSERS: PerBuffer<Parser> = PerBuffer::new();
+/// The buffers that are too large for tree-sitter
+static TURNED_OFF: PerBuffer<LongLines> = PerBuffer::new();
+
+/// [`Hookable`]: Triggers when a [`Buffer`] has been fully parsed
+///
+/// This is triggered after a call to [`TsHandle::ts_parse_full`],
+/// once every tree of the `Buffer`, including those of injected
+/// languages, is up to date.
+///
+/// # Arguments
+///
+/// - The [`Handle`] of said `Buffer`.
+///
+/// [`TsHandle::ts_parse_full`]: crate::TsHandle::ts_parse_full
+pub struct FullyParsed(pub(crate) Handle);
+
+impl Hookable for FullyParsed {
+    type Input<'h> = &'h Handle;
+
+    fn get_input<'h>(&'h mut self, _: &mut Pass) -> Self::Input<'h> {
+        &self.0
+    }
+}
+
+impl PartialEq<Handle> for FullyParsed {
+    fn eq(&self, other: &Handle) -> bool {
+        self.0 == *other
+    }
+}
 
 pub(crate) fn add_parser_hook() {
-    fn async_parse(
-        pa: &mut Pass,
-        handle: &Handle,
-        printed_lines: Vec<Range<usize>>,
-        is_queued: bool,
-    ) -> bool {
-        if let Some(filetype) = handle.filetype(pa)
-            && let Some((parser, buf)) = PARSERS.write(pa, handle)
-            && parser.lang_parts.0 == filetype
-        {
-            if parser.is_parsing && !is_queued {
-                return true;
-            }
+    hook::add::<BufferUpdated>(|pa, handle| {
+        let printed_lines = printed_line_ranges(pa, handle);
+        if async_parse(pa, handle, printed_lines.clone(), false) {
+            return;
+        }
 
-            parser.is_parsing = true;
+        if register_parser(pa, handle) {
+            async_parse(pa, handle, printed_lines, false);
+        }
+    });
+}
+
+/// Registers a [`Parser`] for the [`Buffer`], if it has a language
+///
+/// Returns `false` if tree-sitter is not enabled for said `Buffer`.
+fn register_parser(pa: &mut Pass, handle: &Handle) -> bool {
+    let Some(filetype) = handle.read(pa).filetype() else {
+        return false;
+    };
 
-            let visible_ranges = get_visible_ranges(&printed_lines);
-            let mut parts = TRACKER.parts(buf).unwrap();
+    let Some(lang_parts) = lang_parts_of(filetype, handle) else {
+        return false;
+    };
 
-            apply_changes(&parts, parser);
+    if options().large_file_mode == LargeFileMode::Disable {
+        // The Buffer stays registered in the TRACKER while turned off, so
+        // only the changed lines need to be checked again.
+        if let Some((long_lines, buffer)) = TURNED_OFF.write(pa, handle) {
+            let parts = TRACKER.parts(buffer).unwrap();
+            long_lines.update(&parts);
+            if long_lines.is_large(parts.bytes) {
+                return false;
+            }
 
-            // In this case, the previously sent printed_lines may be outdated and
-            // the TsParsers have been reset, so get new ones.
-            if is_queued && parts.changes.len() > 0 {
-                let printed_lines = handle.printed_line_ranges(pa);
-                return async_parse(pa, handle, printed_lines, is_queued);
+            TURNED_OFF.unregister(pa, handle);
+            context::info!("Buffer is no longer too large, tree-sitter was turned on");
+        } else {
+            let long_lines = LongLines::new(handle.text(pa).bytes());
+            if long_lines.is_large(handle.text(pa).bytes()) {
+                TRACKER.register_buffer(handle.write(pa));
+                TURNED_OFF.register(pa, handle, long_lines);
+                context::warn!("Buffer is too large, tree-sitter was turned off");
+                return false;
             }
+        }
+    }
+
+    let len_bytes = handle.text(pa).len().byte();
+
+    let mut parser = TsParser::new();
+    parser.set_language(lang_parts.1).unwrap();
+
+    TRACKER.register_buffer(handle.write(pa));
+    PARSERS.register(pa, handle, Parser {
+        parser,
+        trees: Trees::new([Ranges::new(0..len_bytes)]),
+        lang_parts,
+        forms: forms_from_lang_parts(lang_parts),
+        injections: Vec::new(),
+        ranges_to_inject: Ranges::new(0..len_bytes),
+        folds: Folds::default(),
+        semantic_highlighting: false,
+        edited: Ranges::empty(),
+        parent_lang: None,
+        selection_history: SelectionHistory::default(),
+        snapshot: Arc::new(Mutex::new(Snapshot::new(len_bytes))),
+        parsed_trees: Vec::new(),
+        large_file: None,
+        long_lines: None,
+        is_parsing: false,
+        full_parse: false,
+    });
+
+    true
+}
+
+fn async_parse(
+    pa: &mut Pass,
+    handle: &Handle,
+    printed_lines: Vec<Range<usize>>,
+    is_queued: bool,
+) -> bool {
+    if let Some(filetype) = handle.filetype(pa)
+        && let Some((parser, _)) = PARSERS.write(pa, handle)
+        && parser.lang_parts.0 == filetype
+    {
+        if parser.is_parsing && !is_queued {
+            return true;
+        }
+
+        parser.is_parsing = true;
+
+        let visible_ranges = get_visible_ranges(&printed_lines);
+        let Some(had_changes) = update_parser(pa, handle, &visible_ranges) else {
+            return true;
+        };
+
+        // In this case, the previously sent printed_lines may be outdated and
+        // the TsParsers have been reset, so get new ones.
+        if is_queued && had_changes {
+            let printed_lines = printed_line_ranges(pa, handle);
+            return async_parse(pa, handle, printed_lines, is_queued);
+        }
+
+        let (parser, buf) = PARSERS.write(pa, handle).unwrap();
+        let mut parts = TRACKER.parts(buf).unwrap();
 
-            if !parser.parse(&mut parts, &visible_ranges, Some(Instant::now()), handle) {
+        let whole_buffer = 0..parts.bytes.len().byte();
+        let parse_ranges = match parser.full_parse {
+            true => std::slice::from_ref(&whole_buffer),
+            false => &visible_ranges[..],
+        };
+
+        let is_fully_parsed =
+            if !parser.parse(&mut parts, parse_ranges, Some(Instant::now()), handle) {
                 let handle = handle.clone();
                 let printed_lines = printed_lines.clone();
-                context::queue(move |pa| _ = async_parse(pa, &handle, printed_lines, true))
+                context::queue(move |pa| _ = async_parse(pa, &handle, printed_lines, true));
+                false
             } else {
                 parser.is_parsing = false;
-            }
+                parser.full_parse && parser.is_fully_parsed()
+            };
 
-            for range in parts
-                .ranges_to_update
-                .select_from(printed_lines.iter().cloned())
-            {
-                let range = range.start..range.end + 1;
-                parts.tags.remove_excl(ts_tagger(), range.clone());
-                parser.highlight(range.clone(), &mut parts);
-                parts.ranges_to_update.update_on([range]);
-            }
+        for range in parts
+            .ranges_to_update
+            .select_from(printed_lines.iter().cloned())
+        {
+            let range = range.start..range.end + 1;
+            parts.tags.remove_excl(ts_tagger(), range.clone());
+            parser.highlight(range.clone(), &mut parts);
+            parser.update_folds(range.clone(), &mut parts);
+            parts.ranges_to_update.update_on([range]);
+        }
 
-            true
-        } else {
-            false
+        if is_fully_parsed {
+            parser.full_parse = false;
+            hook::trigger(pa, FullyParsed(handle.clone()));
         }
+
+        true
+    } else {
+        false
     }
+}
 
-    hook::add::<BufferUpdated>(|pa, handle| {
-        let printed_lines = handle.printed_line_ranges(pa);
-        if async_parse(pa, handle, printed_lines.clone(), false) {
-            return;
-        }
+/// Applies the [`Change`]s of a [`Buffer`] to its [`Parser`]
+///
+/// If the `Buffer` became too large for tree-sitter, it is turned
+/// off, and [`None`] is returned. Otherwise, returns wether there
+/// were any `Change`s.
+fn update_parser(pa: &mut Pass, handle: &Handle, visible_ranges: &[Range<usize>]) -> Option<bool> {
+    let (parser, buffer) = PARSERS.write(pa, handle)?;
+    let mut parts = TRACKER.parts(buffer).unwrap();
+    let had_changes = parts.changes.len() > 0;
 
-        let Some(filetype) = handle.read(pa).filetype() else {
-            return;
-        };
+    apply_changes(&parts, parser);
+    parser.merge_parsed_trees(&mut parts);
 
-        if let Some(lang_parts) = lang_parts_of(filetype, handle) {
-            let len_bytes = handle.text(pa).len().byte();
-
-            let mut parser = TsParser::new();
-            parser.set_language(lang_parts.1).unwrap();
-
-            TRACKER.register_buffer(handle.write(pa));
-            PARSERS.register(pa, handle, Parser {
-                parser,
-                trees: Trees::new([Ranges::new(0..len_bytes)]),
-                lang_parts,
-                forms: forms_from_lang_parts(lang_parts),
-                injections: Vec::new(),
-                ranges_to_inject: Ranges::new(0..len_bytes),
-                is_parsing: false,
-            });
+    if parser.update_large_file(visible_ranges, &parts) {
+        return Some(had_changes);
+    }
 
-            async_parse(pa, handle, printed_lines.clone(), false);
-        }
-    });
+    parts.tags.remove(ts_tagger(), ..);
+    parts.tags.remove(fold_tagger(), ..);
+    let long_lines = parser.long_lines.take().unwrap_or_default();
+
+    PARSERS.unregister(pa, handle);
+    TURNED_OFF.register(pa, handle, long_lines);
+    context::warn!("Buffer is too large, tree-sitter was turned off");
+
+    None
+}
+
+/// Receives a [`TsTree`] parsed on the worker thread
+///
+/// The `TsTree` is merged on the next parse, which is done right
+/// away.
+fn receive_parsed_tree(pa: &mut Pass, handle: &Handle, parsed: ParsedTree) {
+    let Some((parser, _)) = PARSERS.write(pa, handle) else {
+        return;
+    };
+    parser.parsed_trees.push(parsed);
+
+    let printed_lines = printed_line_ranges(pa, handle);
+    async_parse(pa, handle, printed_lines, false);
 }
 
 pub struct Parser {
@@ -119,7 +276,17 @@ pub struct Parser {
     forms: &'static [(FormId, u8)],
     ranges_to_inject: Ranges,
     injections: Vec<Parser>,
+    folds: Folds,
+    semantic_highlighting: bool,
+    edited: Ranges,
+    parent_lang: Option<&'static str>,
+    selection_history: SelectionHistory,
+    snapshot: Arc<Mutex<Snapshot>>,
+    parsed_trees: Vec<ParsedTree>,
+    large_file: Option<bool>,
+    long_lines: Option<LongLines>,
     is_parsing: bool,
+    full_parse: bool,
 }
 
 impl Parser {
@@ -129,6 +296,597 @@ impl Parser {
         tree.ts_tree.as_ref().unwrap().root_node()
     }
 
+    /// The [`Fold`]s of this `Parser`, including those of injections
+    ///
+    /// These are only calculated for parts of the [`Buffer`] that
+    /// have been printed.
+    pub fn folds(&self) -> impl Iterator<Item = &Fold> {
+        self.folds.iter()
+    }
+
+    /// Wether references are highlighted with the forms of their
+    /// [`Definition`]s
+    ///
+    /// [`Definition`]: crate::Definition
+    pub fn semantic_highlighting(&self) -> bool {
+        self.semantic_highlighting
+    }
+
+    /// The [`Locals`] of the tree under a given byte
+    ///
+    /// If the byte is within an injected language, the `Locals` of
+    /// the innermost injection will be returned instead.
+    ///
+    /// Returns [`None`] if the byte hasn't been parsed yet.
+    pub fn locals(&self, byte: usize, bytes: &Bytes) -> Option<Locals> {
+        let (parser, ts_tree) = self.layer_on(byte)?;
+        let (lang, _, Queries { locals, .. }) = parser.lang_parts;
+
+        Some(Locals::new(lang, locals, ts_tree.root_node(), bytes))
+    }
+
+    /// The smallest text object with a given name around a [`Range`]
+    ///
+    /// Text objects come from the `textobjects.scm` query, and have
+    /// names like `function.outer` or `parameter.inner`. All
+    /// captures with that name in a match are joined into a single
+    /// object, so an object can also span some separators.
+    ///
+    /// Text objects from injected languages are also considered.
+    pub fn textobject_on(
+        &self,
+        name: &str,
+        range: Range<usize>,
+        bytes: &Bytes,
+    ) -> Option<Range<usize>> {
+        let mut objects = Vec::new();
+        let search_range = range.start..range.end.max(range.start + 1);
+        self.textobjects_on(name, search_range, bytes, &mut objects);
+
+        objects
+            .into_iter()
+            .filter(|object| object.start <= range.start && range.end <= object.end)
+            .min_by_key(|object| object.len())
+    }
+
+    /// The first text object with a given name that starts after a
+    /// byte
+    ///
+    /// If many objects start on the same byte, the outermost one is
+    /// returned.
+    pub fn next_textobject(&self, name: &str, byte: usize, bytes: &Bytes) -> Option<Range<usize>> {
+        let mut objects = Vec::new();
+        self.textobjects_on(name, byte..bytes.len().byte(), bytes, &mut objects);
+
+        objects
+            .into_iter()
+            .filter(|object| object.start > byte)
+            .min_by(|lhs, rhs| lhs.start.cmp(&rhs.start).then(rhs.end.cmp(&lhs.end)))
+    }
+
+    /// The last text object with a given name that starts before a
+    /// byte
+    ///
+    /// If many objects start on the same byte, the outermost one is
+    /// returned.
+    pub fn prev_textobject(&self, name: &str, byte: usize, bytes: &Bytes) -> Option<Range<usize>> {
+        let mut objects = Vec::new();
+        self.textobjects_on(name, 0..byte, bytes, &mut objects);
+
+        objects
+            .into_iter()
+            .filter(|object| object.start < byte)
+            .max_by(|lhs, rhs| lhs.start.cmp(&rhs.start).then(rhs.end.cmp(&lhs.end)))
+    }
+
+    /// Pushes the text objects with a given name that intersect a
+    /// [`Range`], including those of injections
+    fn textobjects_on(
+        &self,
+        name: &str,
+        range: Range<usize>,
+        bytes: &Bytes,
+        objects: &mut Vec<Range<usize>>,
+    ) {
+        let (.., Queries { textobjects, .. }) = self.lang_parts;
+
+        if let Some(index) = textobjects.capture_index_for_name(name) {
+            for (_, tree) in self.trees.intersecting(range.clone()) {
+                let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                    continue;
+                };
+
+                let mut cursor = QueryCursor::new();
+                cursor.set_byte_range(range.clone());
+                let mut matches = cursor.matches(textobjects, ts_tree.root_node(), TsBuf(bytes));
+
+                while let Some(qm) = matches.next() {
+                    if !satisfies_predicates(textobjects, qm, None) {
+                        continue;
+                    }
+
+                    objects.extend(
+                        qm.captures
+                            .iter()
+                            .filter(|cap| cap.index == index)
+                            .map(|cap| capture_range(textobjects, qm.pattern_index, cap, bytes))
+                            .reduce(|lhs, rhs| lhs.start.min(rhs.start)..lhs.end.max(rhs.end)),
+                    );
+                }
+            }
+        }
+
+        for injection in self.injections.iter() {
+            injection.textobjects_on(name, range.clone(), bytes, objects);
+        }
+    }
+
+    /// The ranges of the scopes around a byte, outermost first
+    ///
+    /// The scopes come from the `@context` captures of `context.scm`.
+    /// For languages without that query, the `@fold` captures of
+    /// `folds.scm` are used instead. Scopes of injected languages are
+    /// included.
+    pub fn context_on(&self, byte: usize, bytes: &Bytes) -> Vec<Range<usize>> {
+        let mut scopes = Vec::new();
+        self.push_context(byte, bytes, &mut scopes);
+
+        scopes.sort_unstable_by(|lhs, rhs| lhs.start.cmp(&rhs.start).then(rhs.end.cmp(&lhs.end)));
+        scopes.dedup();
+        scopes
+    }
+
+    fn push_context(&self, byte: usize, bytes: &Bytes, scopes: &mut Vec<Range<usize>>) {
+        let (.., Queries { context, folds, .. }) = self.lang_parts;
+        let (query, index) = match context.capture_index_for_name("context") {
+            Some(index) => (context, index),
+            None => match folds.capture_index_for_name("fold") {
+                Some(index) => (folds, index),
+                None => return,
+            },
+        };
+
+        let range = byte..byte + 1;
+        for (_, tree) in self.trees.intersecting(range.clone()) {
+            let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                continue;
+            };
+
+            let mut cursor = QueryCursor::new();
+            cursor.set_byte_range(range.clone());
+            let mut captures = cursor.captures(query, ts_tree.root_node(), TsBuf(bytes));
+
+            while let Some((qm, i)) = captures.next() {
+                let cap = qm.captures[*i];
+                if cap.index != index || !satisfies_predicates(query, qm, None) {
+                    continue;
+                }
+
+                let cap_range = capture_range(query, qm.pattern_index, &cap, bytes);
+                if cap_range.contains(&byte) {
+                    scopes.push(cap_range);
+                }
+            }
+        }
+
+        for injection in self.injections.iter() {
+            injection.push_context(byte, bytes, scopes);
+        }
+    }
+
+    /// The [`Symbol`]s defined in the buffer, including those of
+    /// injections
+    ///
+    /// These are sorted by their position, with the depth of each
+    /// one being how many other symbols contain it.
+    pub fn symbols(&self, bytes: &Bytes) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        self.push_symbols(bytes, &mut symbols);
+        outline::nest_symbols(&mut symbols);
+        symbols
+    }
+
+    fn push_symbols(&self, bytes: &Bytes, symbols: &mut Vec<Symbol>) {
+        const IGNORED_KINDS: &[&str] = &["", "var", "parameter", "field", "import", "associated"];
+        let (lang, _, Queries { tags, locals, .. }) = self.lang_parts;
+
+        let has_tags = tags
+            .capture_names()
+            .iter()
+            .any(|name| name.starts_with("definition."));
+
+        for ts_tree in self.trees.iter().filter_map(|tree| tree.ts_tree.as_ref()) {
+            let mut cursor = QueryCursor::new();
+
+            if has_tags {
+                let names = tags.capture_names();
+                let mut matches = cursor.matches(tags, ts_tree.root_node(), TsBuf(bytes));
+
+                while let Some(qm) = matches.next() {
+                    if !satisfies_predicates(tags, qm, None) {
+                        continue;
+                    }
+
+                    let name = qm
+                        .captures
+                        .iter()
+                        .find(|cap| names[cap.index as usize] == "name");
+                    let def = qm.captures.iter().find_map(|cap| {
+                        let kind = names[cap.index as usize].strip_prefix("definition.")?;
+                        Some((kind, cap))
+                    });
+
+                    if let (Some(name), Some((kind, def))) = (name, def) {
+                        let name_range = capture_range(tags, qm.pattern_index, name, bytes);
+                        symbols.push(Symbol {
+                            name: bytes[name_range.clone()].to_string(),
+                            kind,
+                            range: capture_range(tags, qm.pattern_index, def, bytes),
+                            name_range,
+                            lang,
+                            depth: 0,
+                        });
+                    }
+                }
+            } else {
+                let names = locals.capture_names();
+                let mut matches = cursor.matches(locals, ts_tree.root_node(), TsBuf(bytes));
+
+                while let Some(qm) = matches.next() {
+                    if !satisfies_predicates(locals, qm, None) {
+                        continue;
+                    }
+
+                    for cap in qm.captures.iter() {
+                        let Some(kind) = names[cap.index as usize].strip_prefix("local.definition")
+                        else {
+                            continue;
+                        };
+                        let kind = kind.strip_prefix('.').unwrap_or(kind);
+                        if IGNORED_KINDS.contains(&kind) {
+                            continue;
+                        }
+
+                        // The definition itself is the parent of its name.
+                        let name_range = cap.node.byte_range();
+                        let range = cap
+                            .node
+                            .parent()
+                            .map(|parent| parent.byte_range())
+                            .unwrap_or(name_range.clone());
+
+                        symbols.push(Symbol {
+                            name: bytes[name_range.clone()].to_string(),
+                            kind,
+                            range,
+                            name_range,
+                            lang,
+                            depth: 0,
+                        });
+                    }
+                }
+            }
+        }
+
+        for injection in self.injections.iter() {
+            injection.push_symbols(bytes, symbols);
+        }
+    }
+
+    /// The named [`Node`] reached from a [`Range`] through a
+    /// [`NodeMotion`]
+    ///
+    /// The motion starts from the smallest named `Node` that contains
+    /// the `Range`, in the innermost language. Only
+    /// [`NodeMotion::Parent`] can leave an injected language, moving
+    /// to the `Node`s around it.
+    pub fn node_motion(&self, range: Range<usize>, motion: NodeMotion) -> Option<Range<usize>> {
+        for ts_tree in self.trees_on(range.clone()) {
+            let mut cursor = Cursor::new(ts_tree.root_node());
+            let Some(node) = cursor.goto_named_containing(range.clone()) else {
+                continue;
+            };
+
+            let target = match motion {
+                NodeMotion::NextSibling => cursor.next_named_sibling(),
+                NodeMotion::PrevSibling => cursor.prev_named_sibling(),
+                NodeMotion::Parent => loop {
+                    match cursor.named_parent() {
+                        Some(parent) if parent.byte_range() == node.byte_range() => {}
+                        parent => break parent,
+                    }
+                },
+                NodeMotion::FirstChild => cursor.first_named_child(),
+            };
+
+            if target.is_some() || motion != NodeMotion::Parent {
+                return target.map(|node| node.byte_range());
+            }
+        }
+
+        None
+    }
+
+    /// The named [`Node`] under a [`Range`], and its next or
+    /// previous named sibling
+    ///
+    /// Returns the byte `Range`s of both `Node`s, in that order.
+    pub fn node_and_sibling(
+        &self,
+        range: Range<usize>,
+        forward: bool,
+    ) -> Option<(Range<usize>, Range<usize>)> {
+        let ts_tree = self.trees_on(range.clone()).into_iter().next()?;
+
+        let mut cursor = Cursor::new(ts_tree.root_node());
+        let node = cursor.goto_named_containing(range)?;
+        let sibling = if forward {
+            cursor.next_named_sibling()?
+        } else {
+            cursor.prev_named_sibling()?
+        };
+
+        Some((node.byte_range(), sibling.byte_range()))
+    }
+
+    /// The smallest named [`Node`] that strictly contains a [`Range`]
+    ///
+    /// If there is no such `Node` in an injected language, the search
+    /// continues on the trees around the injection, so the `Range`
+    /// can grow past the root of the injection.
+    pub fn expanded_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        self.trees_on(range.clone())
+            .into_iter()
+            .find_map(|ts_tree| {
+                let mut cursor = Cursor::new(ts_tree.root_node());
+                let mut node = cursor.goto_smallest_containing(range.clone());
+
+                loop {
+                    let node_range = node.byte_range();
+                    if node.is_named()
+                        && node_range != range
+                        && node_range.start <= range.start
+                        && range.end <= node_range.end
+                    {
+                        break Some(node_range);
+                    }
+
+                    node = cursor.parent()?;
+                }
+            })
+    }
+
+    /// Expands each selection to the smallest named [`Node`] around
+    /// it
+    ///
+    /// Returns the selections that were expanded, with their new
+    /// [`Range`]s.
+    pub(crate) fn expand_selections(
+        &mut self,
+        selections: Vec<(usize, Range<usize>)>,
+    ) -> Vec<(usize, Range<usize>)> {
+        selections
+            .into_iter()
+            .filter_map(|(n, range)| {
+                let expanded = self.expanded_range(range.clone())?;
+                self.selection_history.push(n, range, expanded.clone());
+                Some((n, expanded))
+            })
+            .collect()
+    }
+
+    /// Shrinks each selection to its [`Range`] before the last
+    /// expansion
+    ///
+    /// Returns the selections that were shrunk, with their new
+    /// `Range`s.
+    pub(crate) fn shrink_selections(
+        &mut self,
+        selections: Vec<(usize, Range<usize>)>,
+    ) -> Vec<(usize, Range<usize>)> {
+        selections
+            .into_iter()
+            .filter_map(|(n, range)| Some((n, self.selection_history.pop(n, range)?)))
+            .collect()
+    }
+
+    /// The [`TsTree`]s on a [`Range`], from the innermost injection
+    /// to the outermost tree
+    fn trees_on(&self, range: Range<usize>) -> Vec<&TsTree> {
+        let mut ts_trees = self
+            .injections
+            .iter()
+            .map(|injection| injection.trees_on(range.clone()))
+            .find(|ts_trees| !ts_trees.is_empty())
+            .unwrap_or_default();
+
+        let search_range = range.start..range.end.max(range.start + 1);
+        ts_trees.extend(
+            self.trees
+                .intersecting(search_range)
+                .filter_map(|(_, tree)| tree.ts_tree.as_ref()),
+        );
+
+        ts_trees
+    }
+
+    /// The [`Language`] of this `Parser`
+    pub fn language(&self) -> &'static Language {
+        self.lang_parts.1
+    }
+
+    /// The captures of a [`Query`] within a [`Range`]
+    ///
+    /// Only the trees of this `Parser` are queried, not those of its
+    /// injections. Predicates and directives are applied, and the
+    /// captures are returned with their indices in the `Query`.
+    pub(crate) fn query_captures(
+        &self,
+        query: &Query,
+        range: Range<usize>,
+        bytes: &Bytes,
+    ) -> Vec<(Range<usize>, u32)> {
+        let mut captures = Vec::new();
+
+        for (_, tree) in self.trees.intersecting(range.clone()) {
+            let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                continue;
+            };
+
+            let mut cursor = QueryCursor::new();
+            cursor.set_byte_range(range.clone());
+            let mut query_captures = cursor.captures(query, ts_tree.root_node(), TsBuf(bytes));
+
+            let locals = tree.semantic.as_ref().map(SemanticForms::locals);
+
+            while let Some((qm, i)) = query_captures.next() {
+                if !satisfies_predicates(query, qm, locals) {
+                    continue;
+                }
+
+                let cap = qm.captures[*i];
+                let cap_range = capture_range(query, qm.pattern_index, &cap, bytes);
+                captures.push((cap_range, cap.index));
+            }
+        }
+
+        captures
+    }
+
+    /// The captures of a [`Query`] within a [`Range`], in every
+    /// layer
+    ///
+    /// The `Query` is compiled for the language of every layer, and
+    /// the layers where it fails to compile are skipped. If it
+    /// doesn't compile for any of them, the error of the outermost
+    /// layer is returned.
+    ///
+    /// The captures are ordered by position, then by layer.
+    pub fn query(
+        &self,
+        query_src: &str,
+        range: Range<usize>,
+        bytes: &Bytes,
+    ) -> Result<Vec<Capture>, Text> {
+        let mut captures: Vec<Capture> = self
+            .query_matches(query_src, range, bytes)?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        captures.sort_by_key(|cap| (cap.range.start, cap.layer));
+        Ok(captures)
+    }
+
+    /// The matches of a [`Query`] within a [`Range`], in every layer
+    ///
+    /// Each match is a list of its captures. Other than that, this
+    /// works just like [`Parser::query`], but the matches are ordered
+    /// by layer, then by position.
+    pub fn query_matches(
+        &self,
+        query_src: &str,
+        range: Range<usize>,
+        bytes: &Bytes,
+    ) -> Result<Vec<Vec<Capture>>, Text> {
+        let mut matches = Vec::new();
+        let mut compiled_any = false;
+        let result =
+            self.push_query_matches(query_src, range, bytes, 0, &mut matches, &mut compiled_any);
+
+        match result {
+            Err(err) if !compiled_any => Err(err),
+            _ => Ok(matches),
+        }
+    }
+
+    fn push_query_matches(
+        &self,
+        query_src: &str,
+        range: Range<usize>,
+        bytes: &Bytes,
+        layer: usize,
+        matches: &mut Vec<Vec<Capture>>,
+        compiled_any: &mut bool,
+    ) -> Result<(), Text> {
+        let (lang, language, _) = self.lang_parts;
+        let result = cached_query(lang, language, query_src).map(|query| {
+            let query = query.as_ref();
+            *compiled_any = true;
+            let names = query.capture_names();
+
+            for (_, tree) in self.trees.intersecting(range.clone()) {
+                let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                    continue;
+                };
+
+                let mut cursor = QueryCursor::new();
+                cursor.set_byte_range(range.clone());
+                let mut query_matches = cursor.matches(query, ts_tree.root_node(), TsBuf(bytes));
+
+                let locals = tree.semantic.as_ref().map(SemanticForms::locals);
+
+                while let Some(qm) = query_matches.next() {
+                    if !satisfies_predicates(query, qm, locals) {
+                        continue;
+                    }
+
+                    matches.push(
+                        qm.captures
+                            .iter()
+                            .map(|cap| Capture {
+                                range: capture_range(query, qm.pattern_index, cap, bytes),
+                                name: names[cap.index as usize].to_string(),
+                                lang,
+                                layer,
+                            })
+                            .collect(),
+                    );
+                }
+            }
+        });
+
+        for injection in self.injections.iter() {
+            _ = injection.push_query_matches(
+                query_src,
+                range.clone(),
+                bytes,
+                layer + 1,
+                matches,
+                compiled_any,
+            );
+        }
+
+        result
+    }
+
+    /// The language and [`TsTree`] of every tree, including those
+    /// of injections
+    pub(crate) fn ts_trees(&self) -> Vec<(&'static str, &TsTree)> {
+        let mut ts_trees: Vec<_> = self
+            .trees
+            .iter()
+            .filter_map(|tree| Some((self.lang_parts.0, tree.ts_tree.as_ref()?)))
+            .collect();
+
+        for injection in self.injections.iter() {
+            ts_trees.extend(injection.ts_trees());
+        }
+
+        ts_trees
+    }
+
+    /// The innermost `Parser` and [`TsTree`] on a given byte
+    fn layer_on(&self, byte: usize) -> Option<(&Parser, &TsTree)> {
+        self.injections
+            .iter()
+            .find_map(|injection| injection.layer_on(byte))
+            .or_else(|| {
+                let (_, tree) = self.trees.intersecting(byte..byte + 1).next()?;
+                Some((self, tree.ts_tree.as_ref()?))
+            })
+    }
+
     fn parse(
         &mut self,
         parts: &mut BufferParts,
@@ -139,23 +897,28 @@ impl Parser {
         let mut parsed_at_least_one_region = false;
 
         for range in visible_ranges.iter() {
-            let Some(parsed_a_tree) = self.parse_trees(range.clone(), parts, start) else {
-                return false;
-            };
-
-            parsed_at_least_one_region |= parsed_a_tree;
+            parsed_at_least_one_region |= self.parse_trees(range.clone(), parts, start, handle);
         }
 
         if parsed_at_least_one_region {
             self.ranges_to_inject.add(0..parts.bytes.len().byte());
         }
 
+        // Large buffers don't get injections, and injections are only
+        // looked for once the trees being parsed in the background arrive.
         let ranges_to_inject = visible_ranges
             .iter()
+            .filter(|_| self.large_file != Some(true))
+            .filter(|range| {
+                !self
+                    .trees
+                    .intersecting((*range).clone())
+                    .any(|(_, tree)| tree.needs_parse)
+            })
             .flat_map(|range| self.ranges_to_inject.iter_over(range.clone()))
             .fold(Vec::<Range<usize>>::new(), |mut ranges, range| {
                 match ranges.last_mut() {
-                    Some(last) if last.end == range.start => last.end = range.end,
+                    Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
                     _ => ranges.push(range),
                 }
                 ranges
@@ -177,72 +940,240 @@ impl Parser {
         true
     }
 
+    /// Parses the [`Tree`]s that intersect a [`Range`]
+    ///
+    /// If `start` is [`Some`], the `Tree`s are sent to be parsed on
+    /// the worker thread, and are merged back on a later parse.
+    /// Otherwise, they are parsed right away.
+    ///
+    /// Returns `true` if any `Tree` was parsed right away.
     fn parse_trees(
         &mut self,
         range: Range<usize>,
         parts: &mut BufferParts,
         start: Option<Instant>,
-    ) -> Option<bool> {
-        let mut callback = |_: &ParseState| match must_yield(start) {
-            true => ControlFlow::Break(()),
-            false => ControlFlow::Continue(()),
-        };
-
+        handle: &Handle,
+    ) -> bool {
+        let bytes = parts.bytes;
         let ts_range = |range: Range<usize>| TsRange {
             start_byte: range.start,
             end_byte: range.end,
-            start_point: ts_point(parts.bytes.point_at_byte(range.start), parts.bytes),
-            end_point: ts_point(parts.bytes.point_at_byte(range.end), parts.bytes),
+            start_point: ts_point(bytes.point_at_byte(range.start), bytes),
+            end_point: ts_point(bytes.point_at_byte(range.end), bytes),
         };
 
-        let mut parsed_at_least_one_region = false;
-        let mut parsing_failed = false;
+        let to_parse: Vec<usize> = self
+            .trees
+            .intersecting(range)
+            .filter(|(_, tree)| tree.needs_parse && (start.is_none() || tree.pending.is_none()))
+            .map(|(n, _)| n)
+            .collect();
+
+        let mut parsed_at_least_one_region = false;
+
+        for n in to_parse {
+            let tree = self.trees.get_mut(n).unwrap();
+            let ts_ranges: Vec<TsRange> = tree.region.iter().map(ts_range).collect();
+
+            if start.is_some() {
+                let snapshot = self.snapshot.lock().unwrap().update(bytes);
+                let handle = handle.clone();
+                let semantic = self
+                    .semantic_highlighting
+                    .then_some((self.lang_parts, self.forms));
+
+                tree.pending = Some(worker::parse_in_background(
+                    self.lang_parts.1,
+                    ts_ranges,
+                    tree.ts_tree.clone(),
+                    snapshot,
+                    semantic,
+                    move |parsed| {
+                        context::queue(move |pa| receive_parsed_tree(pa, &handle, parsed))
+                    },
+                ));
+                continue;
+            }
+
+            // Parsing right away makes any background parse outdated.
+            tree.pending = None;
+            self.parser.set_included_ranges(&ts_ranges).unwrap();
+
+            let Some(new_ts_tree) =
+                self.parser
+                    .parse_with_options(&mut parser_fn(bytes), tree.ts_tree.as_ref(), None)
+            else {
+                continue;
+            };
+
+            self.set_ts_tree(n, new_ts_tree, None, false, parts);
+            parsed_at_least_one_region = true;
+        }
+
+        parsed_at_least_one_region
+    }
+
+    /// Replaces the [`TsTree`] of the `n`th [`Tree`]
+    ///
+    /// The ranges that changed between the two `TsTree`s are
+    /// added to the ranges to update. If the [`SemanticForms`] of the
+    /// new `TsTree` weren't computed on the worker thread, they are
+    /// computed here.
+    ///
+    /// If `is_behind`, the new `TsTree` was edited after being
+    /// parsed, so the `Tree` is left to be parsed again, keeping
+    /// its old `SemanticForms` until then.
+    fn set_ts_tree(
+        &mut self,
+        n: usize,
+        new_ts_tree: TsTree,
+        semantic: Option<SemanticForms>,
+        is_behind: bool,
+        parts: &mut BufferParts,
+    ) {
+        let tree = self.trees.get_mut(n).unwrap();
+
+        let mut changed_ranges: Vec<Range<usize>> = match tree.ts_tree.as_ref() {
+            Some(ts_tree) => ts_tree
+                .changed_ranges(&new_ts_tree)
+                .map(|r| r.start_byte..r.end_byte)
+                .collect(),
+            None => tree.region.iter().collect(),
+        };
+
+        if self.semantic_highlighting && !is_behind {
+            let semantic = semantic.unwrap_or_else(|| {
+                semantic_forms_of(self.lang_parts, self.forms, &new_ts_tree, parts.bytes)
+            });
+
+            // Changes to a definition will change the forms of references
+            // outside of the changed ranges, so their scopes are updated.
+            for range in tree.region.iter() {
+                changed_ranges.extend(self.edited.remove_on(range));
+            }
+            let scopes: Vec<Range<usize>> = changed_ranges
+                .iter()
+                .flat_map(|range| semantic.scopes_affected_by(range.clone()))
+                .collect();
+
+            parts.ranges_to_update.add_ranges(scopes);
+            tree.semantic = Some(semantic);
+        }
+
+        tree.ts_tree = Some(new_ts_tree);
+
+        parts.ranges_to_update.add_ranges(changed_ranges);
+
+        tree.needs_parse = is_behind;
+    }
+
+    /// Switches between normal parsing and the [`LargeFileMode`]
+    ///
+    /// Returns `false` if tree-sitter should be turned off for the
+    /// buffer, which only happens with [`LargeFileMode::Disable`].
+    fn update_large_file(&mut self, visible_ranges: &[Range<usize>], parts: &BufferParts) -> bool {
+        let bytes = parts.bytes;
+        let is_large = match &mut self.long_lines {
+            Some(long_lines) => {
+                long_lines.update(parts);
+                long_lines.is_large(bytes)
+            }
+            None => self
+                .long_lines
+                .insert(LongLines::new(bytes))
+                .is_large(bytes),
+        };
+
+        let was_large = self.large_file.replace(is_large) == Some(true);
+        let len = bytes.len().byte();
+
+        if was_large && !is_large {
+            self.trees = Trees::new([Ranges::new(0..len)]);
+            self.ranges_to_inject = Ranges::new(0..len);
+            return true;
+        } else if !is_large {
+            return true;
+        } else if !was_large {
+            self.injections.clear();
+        }
+
+        match options().large_file_mode {
+            LargeFileMode::Window { lines } => {
+                let is_covered = visible_ranges.iter().all(|range| {
+                    self.trees.iter().any(|tree| {
+                        tree.region
+                            .iter()
+                            .any(|region| region.start <= range.start && range.end <= region.end)
+                    })
+                });
 
-        for (_, tree) in self.trees.intersecting_mut(range.clone()) {
-            if !tree.needs_parse {
-                continue;
-            }
+                if !was_large || !is_covered {
+                    let windows = large_file::windows_around(bytes, visible_ranges, lines);
+                    self.trees = Trees::new(windows.into_iter().map(Ranges::new));
+                }
 
-            // Almost all injections fall here.
-            if tree.region.len() == 1 {
-                let ts_range = ts_range(tree.region.iter().next().unwrap());
-                self.parser.set_included_ranges(&[ts_range]).unwrap();
-            } else {
-                let ts_ranges: Vec<_> = tree.region.iter().map(ts_range).collect();
-                self.parser.set_included_ranges(&ts_ranges).unwrap();
+                true
             }
+            LargeFileMode::NoInjections => true,
+            LargeFileMode::Disable => false,
+        }
+    }
 
-            let Some(new_ts_tree) = self.parser.parse_with_options(
-                &mut parser_fn(parts.bytes),
-                tree.ts_tree.as_ref(),
-                Some(ParseOptions::new().progress_callback(&mut callback)),
-            ) else {
-                parsing_failed = true;
-                break;
-            };
+    /// Wether every [`Tree`] of this `Parser` and its injections is
+    /// up to date
+    fn is_fully_parsed(&self) -> bool {
+        self.trees.iter().all(|tree| !tree.needs_parse)
+            && self.injections.iter().all(Parser::is_fully_parsed)
+    }
 
-            if let Some(ts_tree) = tree.ts_tree.as_mut() {
-                parts.ranges_to_update.add_ranges(
-                    ts_tree
-                        .changed_ranges(&new_ts_tree)
-                        .map(|r| r.start_byte..r.end_byte),
-                );
+    /// Merges the [`TsTree`]s that were parsed on the worker thread
+    ///
+    /// `TsTree`s of [`Tree`]s that were removed, or whose parses
+    /// were cancelled by an edit, are thrown away. Other edits made
+    /// in the meantime are applied to the `TsTree`s, and their
+    /// `Tree`s are sent to be parsed again.
+    fn merge_parsed_trees(&mut self, parts: &mut BufferParts) {
+        for parsed in std::mem::take(&mut self.parsed_trees) {
+            self.merge_parsed_tree(parsed, parts);
+        }
+    }
 
-                *ts_tree = new_ts_tree;
-            } else {
-                parts.ranges_to_update.add_ranges(tree.region.iter());
-                tree.ts_tree = Some(new_ts_tree);
+    /// Merges a [`ParsedTree`] into this `Parser` or its injections
+    ///
+    /// Returns the `ParsedTree` back if no [`Tree`] was waiting for
+    /// it.
+    fn merge_parsed_tree(
+        &mut self,
+        parsed: ParsedTree,
+        parts: &mut BufferParts,
+    ) -> Option<ParsedTree> {
+        let waiting = self.trees.iter().position(|tree| {
+            tree.pending
+                .as_ref()
+                .is_some_and(|pending| pending.id() == parsed.id)
+        });
+
+        if let Some(n) = waiting {
+            let pending = self.trees.get_mut(n).unwrap().pending.take().unwrap();
+
+            let mut ts_tree = parsed.ts_tree;
+            for edit in pending.edits() {
+                ts_tree.edit(edit);
             }
 
-            tree.needs_parse = false;
-            parsed_at_least_one_region = true;
-        }
+            let is_behind = !pending.edits().is_empty();
+            self.set_ts_tree(n, ts_tree, parsed.semantic, is_behind, parts);
+            self.ranges_to_inject.add(0..parts.bytes.len().byte());
 
-        if parsing_failed {
             return None;
         }
 
-        Some(parsed_at_least_one_region)
+        let mut parsed = parsed;
+        for injection in self.injections.iter_mut() {
+            parsed = injection.merge_parsed_tree(parsed, parts)?;
+        }
+
+        Some(parsed)
     }
 
     fn highlight(&self, range: Range<usize>, parts: &mut BufferParts) {
@@ -262,18 +1193,33 @@ impl Parser {
             cursor.set_byte_range(range.clone());
             let mut hi_captures = cursor.captures(highlights, ts_tree.root_node(), buf);
 
+            let locals = tree.semantic.as_ref().map(SemanticForms::locals);
+
             while let Some((qm, _)) = hi_captures.next() {
                 let qm: &QueryMatch = qm;
+                if !satisfies_predicates(highlights, qm, locals) {
+                    continue;
+                }
+
                 for cap in qm.captures.iter() {
-                    let ts_range = cap.node.range();
+                    let range = capture_range(highlights, qm.pattern_index, cap, parts.bytes);
 
                     // Assume that an empty range must take up the whole line
                     // Cuz sometimes it be like that
                     let (form, priority) = self.forms[cap.index as usize];
-                    let range = ts_range.start_byte..ts_range.end_byte;
                     parts.tags.insert(tagger, range, form.to_tag(priority));
                 }
             }
+
+            if !tree.needs_parse
+                && let Some(semantic) = tree.semantic.as_ref()
+            {
+                for (range, form) in semantic.forms_on(range.clone()) {
+                    parts
+                        .tags
+                        .insert(tagger, range, form.to_tag(semantic_priority()));
+                }
+            }
         }
 
         for injection in self.injections.iter() {
@@ -281,6 +1227,99 @@ impl Parser {
         }
     }
 
+    /// The `highlights.scm` captures on a byte, in every layer
+    ///
+    /// If semantic highlighting is enabled, the forms that references
+    /// take from their definitions are included as well. These are
+    /// ordered by priority, so the last one is the one drawn on top.
+    /// Captures with the same priority are ordered from the outermost
+    /// layer to the innermost one, and by position within each layer.
+    pub fn highlight_captures_on(&self, byte: usize, bytes: &Bytes) -> Vec<HighlightCapture> {
+        self.highlight_captures_in(byte..byte + 1, bytes)
+    }
+
+    /// The `highlights.scm` captures that intersect a [`Range`], in
+    /// every layer
+    ///
+    /// These are ordered like in [`Parser::highlight_captures_on`].
+    pub(crate) fn highlight_captures_in(
+        &self,
+        range: Range<usize>,
+        bytes: &Bytes,
+    ) -> Vec<HighlightCapture> {
+        let mut captures = Vec::new();
+        self.push_highlight_captures(range, bytes, 0, &mut captures);
+        captures.sort_by_key(|cap| cap.priority);
+        captures
+    }
+
+    fn push_highlight_captures(
+        &self,
+        range: Range<usize>,
+        bytes: &Bytes,
+        layer: usize,
+        captures: &mut Vec<HighlightCapture>,
+    ) {
+        let (lang, _, Queries { highlights, .. }) = self.lang_parts;
+
+        for (_, tree) in self.trees.intersecting(range.clone()) {
+            let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                continue;
+            };
+
+            let mut cursor = QueryCursor::new();
+            cursor.set_byte_range(range.clone());
+            let mut hi_captures = cursor.captures(highlights, ts_tree.root_node(), TsBuf(bytes));
+
+            let locals = tree.semantic.as_ref().map(SemanticForms::locals);
+
+            while let Some((qm, i)) = hi_captures.next() {
+                if !satisfies_predicates(highlights, qm, locals) {
+                    continue;
+                }
+
+                let cap = qm.captures[*i];
+                let cap_range = capture_range(highlights, qm.pattern_index, &cap, bytes);
+                if cap_range.start >= range.end || cap_range.end <= range.start {
+                    continue;
+                }
+
+                let (form, priority) = self.forms[cap.index as usize];
+                captures.push(HighlightCapture {
+                    range: cap_range,
+                    name: highlights.capture_names()[cap.index as usize],
+                    lang,
+                    layer,
+                    form,
+                    priority,
+                    pattern_index: Some(qm.pattern_index),
+                    source: crate::pattern_source(lang, "highlights", highlights, qm.pattern_index),
+                });
+            }
+
+            if !tree.needs_parse
+                && let Some(semantic) = tree.semantic.as_ref()
+            {
+                for (ref_range, form) in semantic.forms_on(range.clone()) {
+                    captures.push(HighlightCapture {
+                        range: ref_range,
+                        name: "local.reference",
+                        lang,
+                        layer,
+                        form,
+                        priority: semantic_priority(),
+                        pattern_index: None,
+                        source: None,
+                    });
+                }
+            }
+        }
+
+        for injection in self.injections.iter() {
+            injection.push_highlight_captures(range.clone(), bytes, layer + 1, captures);
+        }
+    }
+
     fn inject(&mut self, range: Range<usize>, parts: &mut BufferParts, handle: &Handle) {
         let range = self
             .injections
@@ -289,8 +1328,11 @@ impl Parser {
                 let range = range.clone();
                 move |inj| inj.trees.intersecting(range.clone())
             })
-            .fold(range, |range, (_, tree)| {
-                let inj_range = tree.region.iter().next().unwrap();
+            .flat_map({
+                let range = range.clone();
+                move |(_, tree)| tree.region.iter_intersecting(range.clone())
+            })
+            .fold(range, |range, inj_range| {
                 range.start.min(inj_range.start)..range.end.max(inj_range.end)
             });
 
@@ -299,6 +1341,8 @@ impl Parser {
 
         let cn = injections.capture_names();
         let is_content = |cap: &&QueryCapture| cn[cap.index as usize] == "injection.content";
+        let has_prop = |props: &[QueryProperty], key: &str| props.iter().any(|p| *p.key == *key);
+        let (self_lang, parent_lang) = (self.lang_parts.0, self.parent_lang);
         let language = |qm: &QueryMatch, props: &[QueryProperty]| {
             props
                 .iter()
@@ -306,80 +1350,230 @@ impl Parser {
                     (p.key.as_ref() == "injection.language")
                         .then_some(p.value.as_ref().unwrap().to_string())
                 })
+                .or_else(|| {
+                    injections
+                        .general_predicates(qm.pattern_index)
+                        .iter()
+                        .find_map(|pred| {
+                            let resolve = match pred.operator.as_ref() {
+                                "set-lang-from-info-string!" => lang_from_info_string,
+                                "set-lang-from-mimetype!" => lang_from_mimetype,
+                                _ => return None,
+                            };
+                            let [QueryPredicateArg::Capture(index)] = pred.args.as_ref() else {
+                                return None;
+                            };
+
+                            let cap = qm.captures.iter().find(|cap| cap.index == *index)?;
+                            resolve(&capture_text(injections, qm, cap, parts.bytes))
+                        })
+                })
+                .or_else(|| {
+                    if has_prop(props, "injection.self") {
+                        Some(self_lang.to_string())
+                    } else if has_prop(props, "injection.parent") {
+                        Some(parent_lang.unwrap_or(self_lang).to_string())
+                    } else {
+                        None
+                    }
+                })
                 .or_else(|| {
                     let cap = qm
                         .captures
                         .iter()
                         .find(|cap| cn[cap.index as usize] == "injection.language")?;
-                    Some(parts.bytes[cap.node.byte_range()].to_string())
+                    let name = capture_text(injections, qm, cap, parts.bytes);
+                    Some(lang_from_info_string(&name).unwrap_or(name))
                 })
         };
+        let lang_parts_for = |filetype: &str, props: &[QueryProperty]| {
+            let mut lang_parts = lang_parts_of(filetype, handle)?;
+
+            // You may want to set a new injections query, only for this capture.
+            if let Some(prop) = props.iter().find(|p| p.key.as_ref() == "injection.query")
+                && let Some(value) = prop.value.as_ref()
+            {
+                match query_from_path(filetype, value, lang_parts.1) {
+                    Ok(injections) => {
+                        lang_parts.2.injections = injections;
+                    }
+                    Err(err) => context::error!("{err}"),
+                }
+            };
+
+            Some(lang_parts)
+        };
 
         let mut cursor = QueryCursor::new();
         let mut observed_injections = Vec::new();
+        let mut combined_injections = Vec::new();
         let mut defered_ranges = Vec::new();
 
         for (_, tree) in self.trees.intersecting(range.clone()) {
-            let ts_tree = tree.ts_tree.as_ref().unwrap();
+            let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                continue;
+            };
+
+            // Combined injections that were already in this range need to be
+            // recalculated, since they might not be anymore.
+            let mut combined_patterns: Vec<usize> = self
+                .injections
+                .iter()
+                .flat_map(|inj| inj.trees.intersecting(range.clone()))
+                .filter(|(_, inj_tree)| {
+                    inj_tree
+                        .region
+                        .iter()
+                        .any(|range| tree.region.intersects_with(range))
+                })
+                .filter_map(|(_, inj_tree)| inj_tree.combined_pattern_index)
+                .collect();
 
             cursor.set_byte_range(range.clone());
 
             let mut inj_captures = cursor.captures(injections, ts_tree.root_node(), buf);
 
             while let Some((qm, _)) = inj_captures.next() {
+                if !satisfies_predicates(injections, qm, None) {
+                    continue;
+                }
+
                 let Some(cap) = qm.captures.iter().find(is_content) else {
                     continue;
                 };
 
-                let cap_range = cap.node.byte_range();
+                let cap_range = capture_range(injections, qm.pattern_index, cap, parts.bytes);
                 let props = injections.property_settings(qm.pattern_index);
 
+                if has_prop(props, "injection.combined") {
+                    combined_patterns.push(qm.pattern_index);
+                    continue;
+                }
+
                 let Some(filetype) = language(qm, props) else {
                     continue;
                 };
 
-                let Some(mut lang_parts) = lang_parts_of(&filetype, handle) else {
+                let Some(lang_parts) = lang_parts_for(&filetype, props) else {
                     defered_ranges.push(cap_range);
                     continue;
                 };
 
-                // You may want to set a new injections query, only for this capture.
-                if let Some(prop) = props.iter().find(|p| p.key.as_ref() == "injection.query")
-                    && let Some(value) = prop.value.as_ref()
+                let include_children = has_prop(props, "injection.include-children");
+                let region = injection_region(cap.node, cap_range, include_children);
+                if !region.is_empty() {
+                    observed_injections.push((lang_parts, region));
+                }
+            }
+
+            if combined_patterns.is_empty() {
+                continue;
+            }
+
+            combined_patterns.sort_unstable();
+            combined_patterns.dedup();
+
+            // Combined injections are made out of every match of a pattern
+            // in the tree, not just the ones in the range.
+            let mut fragments: Vec<(usize, LangParts, Vec<Range<usize>>)> = Vec::new();
+            let mut cursor = QueryCursor::new();
+            let mut inj_matches = cursor.matches(injections, ts_tree.root_node(), buf);
+
+            while let Some(qm) = inj_matches.next() {
+                if combined_patterns.binary_search(&qm.pattern_index).is_err()
+                    || !satisfies_predicates(injections, qm, None)
                 {
-                    match query_from_path(&filetype, value, lang_parts.1) {
-                        Ok(injections) => {
-                            lang_parts.2.injections = injections;
-                        }
-                        Err(err) => context::error!("{err}"),
-                    }
+                    continue;
+                }
+
+                let Some(cap) = qm.captures.iter().find(is_content) else {
+                    continue;
                 };
 
-                if let Some(injection) = self
-                    .injections
+                let props = injections.property_settings(qm.pattern_index);
+                let Some(filetype) = language(qm, props) else {
+                    continue;
+                };
+
+                let cap_range = capture_range(injections, qm.pattern_index, cap, parts.bytes);
+
+                let Some(lang_parts) = lang_parts_for(&filetype, props) else {
+                    defered_ranges.push(cap_range);
+                    continue;
+                };
+
+                let include_children = has_prop(props, "injection.include-children");
+                let region = injection_region(cap.node, cap_range, include_children);
+
+                match fragments
                     .iter_mut()
-                    .find(|injection| injection.lang_parts.0 == lang_parts.0)
+                    .find(|(i, lp, _)| *i == qm.pattern_index && lp.0 == lang_parts.0)
                 {
-                    if injection.trees.add_region(Ranges::new(cap_range.clone())) {
-                        parts.ranges_to_update.add_ranges([cap_range.clone()]);
-                    }
-                } else {
-                    let mut parser = TsParser::new();
-                    parser.set_language(lang_parts.1).unwrap();
-                    self.injections.push(Parser {
-                        parser,
-                        trees: Trees::new([Ranges::new(cap_range.clone())]),
-                        lang_parts,
-                        forms: forms_from_lang_parts(lang_parts),
-                        ranges_to_inject: Ranges::new(0..parts.bytes.len().byte()),
-                        injections: Vec::new(),
-                        is_parsing: false,
-                    });
+                    Some((.., ranges)) => ranges.extend(region.iter()),
+                    None => fragments.push((qm.pattern_index, lang_parts, region.iter().collect())),
+                }
+            }
+
+            for pattern_index in combined_patterns {
+                let mut langs = Vec::new();
+
+                for (_, lang_parts, ranges) in
+                    fragments.iter().filter(|(i, ..)| *i == pattern_index)
+                {
+                    let mut region = Ranges::empty();
+                    region.extend(ranges.iter().cloned());
+
+                    combined_injections.push((
+                        *lang_parts,
+                        pattern_index,
+                        tree.region.clone(),
+                        region,
+                    ));
+                    langs.push(lang_parts.0);
+                }
 
-                    parts.ranges_to_update.add_ranges([cap_range.clone()]);
+                // No fragment is of these languages anymore, so their Trees
+                // should be removed.
+                for injection in self.injections.iter().filter(|injection| {
+                    !langs.contains(&injection.lang_parts.0)
+                        && injection
+                            .trees
+                            .iter()
+                            .any(|tree| tree.combined_pattern_index == Some(pattern_index))
+                }) {
+                    combined_injections.push((
+                        injection.lang_parts,
+                        pattern_index,
+                        tree.region.clone(),
+                        Ranges::empty(),
+                    ));
+                }
+            }
+        }
+
+        for (lang_parts, region) in observed_injections.iter() {
+            let injection = self.injection_for(*lang_parts, parts.bytes);
+            if injection.trees.add_region(region.clone(), None) {
+                parts.ranges_to_update.add_ranges(region.iter());
+            }
+        }
+
+        for (lang_parts, pattern_index, within, region) in combined_injections {
+            let injection = if region.is_empty() {
+                let Some(injection) = self
+                    .injections
+                    .iter_mut()
+                    .find(|injection| injection.lang_parts.0 == lang_parts.0)
+                else {
+                    continue;
                 };
+                injection
+            } else {
+                self.injection_for(lang_parts, parts.bytes)
+            };
 
-                observed_injections.push((lang_parts.0, cap_range.clone()));
+            if injection.trees.set_combined(pattern_index, &within, region) {
+                parts.ranges_to_update.add_ranges(within.iter());
             }
         }
 
@@ -387,23 +1581,28 @@ impl Parser {
             let mut to_remove = Vec::new();
 
             for (i, tree) in injection.trees.intersecting(range.clone()) {
-                // TODO: Deal with combined injections
-                let range = tree.region.iter().next().unwrap();
+                // Combined injections are dealt with separately.
+                if tree.combined_pattern_index.is_some() {
+                    continue;
+                }
+
                 if observed_injections
-                    .extract_if(.., |(filetype, r)| {
-                        *r == range && *filetype == injection.lang_parts.0
+                    .extract_if(.., |(lang_parts, region)| {
+                        *region == tree.region && lang_parts.0 == injection.lang_parts.0
                     })
                     .next()
                     .is_none()
                 {
                     parts.ranges_to_update.add_ranges(tree.region.iter());
-                    to_remove.push((i, range));
+                    to_remove.push((i, tree.region.clone()));
                 }
             }
 
-            for (i, range) in to_remove.into_iter().rev() {
+            for (i, region) in to_remove.into_iter().rev() {
                 injection.trees.remove(i);
-                injection.remove_injections_on(range);
+                for range in region.iter() {
+                    injection.remove_injections_on(range);
+                }
             }
         }
 
@@ -414,6 +1613,151 @@ impl Parser {
         }
     }
 
+    /// The injected `Parser` for a language, creating it if needed
+    fn injection_for(&mut self, lang_parts: LangParts<'static>, bytes: &Bytes) -> &mut Parser {
+        if let Some(i) = self
+            .injections
+            .iter()
+            .position(|injection| injection.lang_parts.0 == lang_parts.0)
+        {
+            return &mut self.injections[i];
+        }
+
+        let mut parser = TsParser::new();
+        parser.set_language(lang_parts.1).unwrap();
+        self.injections.push(Parser {
+            parser,
+            trees: Trees::new([]),
+            lang_parts,
+            forms: forms_from_lang_parts(lang_parts),
+            ranges_to_inject: Ranges::new(0..bytes.len().byte()),
+            injections: Vec::new(),
+            folds: Folds::default(),
+            semantic_highlighting: self.semantic_highlighting,
+            edited: Ranges::empty(),
+            parent_lang: Some(self.lang_parts.0),
+            selection_history: SelectionHistory::default(),
+            snapshot: self.snapshot.clone(),
+            parsed_trees: Vec::new(),
+            large_file: None,
+            long_lines: None,
+            is_parsing: false,
+            full_parse: false,
+        });
+
+        self.injections.last_mut().unwrap()
+    }
+
+    /// Updates the [`Fold`]s that start within a [`Range`]
+    ///
+    /// Only the root `Parser` keeps track of `Fold`s, but the ones
+    /// from injections are included in it.
+    fn update_folds(&mut self, range: Range<usize>, parts: &mut BufferParts) {
+        let mut fold_ranges = Vec::new();
+        self.fold_ranges_on(range.clone(), parts.bytes, &mut fold_ranges);
+
+        if self.folds.replace_on(range, fold_ranges) {
+            self.folds.retag(&mut parts.tags, parts.bytes);
+        }
+    }
+
+    fn fold_ranges_on(&self, range: Range<usize>, bytes: &Bytes, ranges: &mut Vec<Range<usize>>) {
+        let buf = TsBuf(bytes);
+        let (.., Queries { folds, .. }) = self.lang_parts;
+
+        for (_, tree) in self.trees.intersecting(range.clone()) {
+            let Some(ts_tree) = tree.ts_tree.as_ref() else {
+                continue;
+            };
+
+            let mut cursor = QueryCursor::new();
+            cursor.set_byte_range(range.clone());
+            let mut fold_captures = cursor.captures(folds, ts_tree.root_node(), buf);
+
+            while let Some((qm, i)) = fold_captures.next() {
+                let cap = qm.captures[*i];
+                if folds.capture_names()[cap.index as usize] != "fold"
+                    || !satisfies_predicates(folds, qm, None)
+                {
+                    continue;
+                }
+
+                let cap_range = capture_range(folds, qm.pattern_index, &cap, bytes);
+
+                // Folds that fit in a single line are pointless.
+                let start = bytes.point_at_byte(cap_range.start);
+                let end = bytes.point_at_byte(cap_range.end.saturating_sub(1).max(cap_range.start));
+
+                if end.line() > start.line() && range.contains(&cap_range.start) {
+                    ranges.push(cap_range);
+                }
+            }
+        }
+
+        for injection in self.injections.iter() {
+            injection.fold_ranges_on(range.clone(), bytes, ranges);
+        }
+    }
+
+    /// Opens or closes the [`Fold`]s on each line given
+    ///
+    /// Returns the number of `Fold`s that were changed.
+    pub(crate) fn set_folds_on(
+        &mut self,
+        lines: impl IntoIterator<Item = usize>,
+        action: FoldAction,
+        parts: &mut BufferParts,
+    ) -> usize {
+        let changed = lines
+            .into_iter()
+            .filter(|lnum| {
+                let line = parts.bytes.line(*lnum).byte_range();
+                self.folds.set_on_line(line, action)
+            })
+            .count();
+
+        if changed > 0 {
+            self.folds.retag(&mut parts.tags, parts.bytes);
+        }
+
+        changed
+    }
+
+    /// Opens or closes every [`Fold`]
+    pub(crate) fn set_all_folds(&mut self, is_closed: bool, parts: &mut BufferParts) {
+        self.folds.set_all(is_closed);
+        self.folds.retag(&mut parts.tags, parts.bytes);
+    }
+
+    /// Enables or disables semantic highlighting
+    ///
+    /// This is also done for every injection, and the whole
+    /// [`Buffer`] is highlighted again.
+    pub(crate) fn set_semantic_highlighting(&mut self, enabled: bool, parts: &mut BufferParts) {
+        self.semantic_highlighting = enabled;
+        self.edited = Ranges::empty();
+
+        for tree in self.trees.iter_mut() {
+            tree.semantic = if enabled && let Some(ts_tree) = tree.ts_tree.as_ref() {
+                Some(semantic_forms_of(
+                    self.lang_parts,
+                    self.forms,
+                    ts_tree,
+                    parts.bytes,
+                ))
+            } else {
+                None
+            };
+        }
+
+        for injection in self.injections.iter_mut() {
+            injection.set_semantic_highlighting(enabled, parts);
+        }
+
+        let whole_buffer = 0..parts.bytes.len().byte();
+        parts.ranges_to_update.add_ranges([whole_buffer]);
+    }
+
     fn remove_injections_on(&mut self, range: Range<usize>) {
         for injection in self.injections.iter_mut() {
             let trees: Vec<usize> = injection
@@ -465,9 +1809,11 @@ impl Parser {
 
         type Captures<'a> = HashMap<&'a str, HashMap<usize, HashMap<&'a str, Option<&'a str>>>>;
         let mut caps = HashMap::new();
+        let mut offsets = HashMap::new();
         let q = {
             cursor
                 .matches(indents, root, buf)
+                .filter(|qm| satisfies_predicates(indents, qm, None))
                 .for_each(|qm: &QueryMatch| {
                     for cap in qm.captures.iter() {
                         let Some(name) =
@@ -476,6 +1822,11 @@ impl Parser {
                             continue;
                         };
 
+                        let offset = row_offsets(indents, qm.pattern_index, cap);
+                        if offset != (0, 0) {
+                            offsets.insert(cap.node.id(), offset);
+                        }
+
                         let nodes = if let Some(nodes) = caps.get_mut(name) {
                             nodes
                         } else {
@@ -562,8 +1913,9 @@ impl Parser {
 
         let mut processed_lines = Vec::new();
         while let Some(node) = opt_node {
-            let s_line = node.start_position().row;
-            let e_line = node.end_position().row;
+            let (s_offset, e_offset) = offsets.get(&node.id()).copied().unwrap_or_default();
+            let s_line = (node.start_position().row as i32 + s_offset).max(0) as usize;
+            let e_line = (node.end_position().row as i32 + e_offset).max(0) as usize;
 
             // If a node is not an indent and is marked as auto or ignore, act
             // accordingly.
@@ -703,6 +2055,14 @@ impl Parser {
     fn edit(&mut self, edit: &InputEdit) {
         self.parser.reset();
         self.trees.edit(edit);
+        self.folds.edit(edit);
+        self.selection_history.clear();
+
+        if self.semantic_highlighting {
+            let shift = edit.new_end_byte as i32 - edit.old_end_byte as i32;
+            self.edited.shift_by(edit.start_byte, shift);
+            self.edited.add(edit.start_byte..edit.new_end_byte + 1);
+        }
 
         for (_, tree) in self
             .trees
@@ -711,27 +2071,106 @@ impl Parser {
             tree.needs_parse = true;
         }
 
+        // The Snapshot is shared with every injection.
+        if self.parent_lang.is_none() {
+            self.snapshot.lock().unwrap().edit(edit);
+        }
+
         for injection in self.injections.iter_mut() {
             injection.edit(edit);
         }
     }
 }
 
+/// The [`Ranges`] of an `@injection.content` [`Node`]
+///
+/// The `range` is that of the `Node`, with `#offset!` applied.
+/// Unless `include_children` is set, the ranges of the named
+/// children of the `Node` are left out.
+fn injection_region(node: Node, range: Range<usize>, include_children: bool) -> Ranges {
+    if range.is_empty() {
+        return Ranges::empty();
+    }
+
+    let mut region = Ranges::new(range);
+
+    if !include_children {
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            _ = region.remove_on(child.byte_range());
+        }
+    }
+
+    region
+}
+
 /// Does a forced parsing of the handle
 pub(crate) fn sync_parse<'p>(
     pa: &'p mut Pass,
     handle: &'p Handle,
 ) -> Option<(&'p Parser, &'p Buffer)> {
-    let printed_lines = handle.printed_line_ranges(pa);
+    let printed_lines = printed_line_ranges(pa, handle);
     let visible_ranges = get_visible_ranges(&printed_lines);
+    update_parser(pa, handle, &visible_ranges)?;
+
     let (parser, buffer) = PARSERS.write(pa, handle)?;
+    let mut parts = TRACKER.parts(buffer).unwrap();
+    parser.parse(&mut parts, &visible_ranges, None, handle);
+
+    Some((parser, buffer))
+}
+
+/// Parses the whole [`Buffer`], including every injected language
+///
+/// If `in_background` is `true`, the trees are parsed on the worker
+/// thread, and [`FullyParsed`] is triggered once all of them are up
+/// to date. Otherwise, it is triggered before returning.
+pub(crate) fn parse_full(pa: &mut Pass, handle: &Handle, in_background: bool) -> Option<()> {
+    if PARSERS.write(pa, handle).is_none() && !register_parser(pa, handle) {
+        return None;
+    }
+
+    let printed_lines = printed_line_ranges(pa, handle);
 
+    if in_background {
+        PARSERS.write(pa, handle)?.0.full_parse = true;
+        async_parse(pa, handle, printed_lines, false);
+        return Some(());
+    }
+
+    let visible_ranges = get_visible_ranges(&printed_lines);
+    update_parser(pa, handle, &visible_ranges)?;
+
+    let (parser, buffer) = PARSERS.write(pa, handle)?;
     let mut parts = TRACKER.parts(buffer).unwrap();
+    let whole_buffer = 0..parts.bytes.len().byte();
+    parser.parse(&mut parts, &[whole_buffer], None, handle);
+    parser.full_parse = false;
 
-    apply_changes(&parts, parser);
+    hook::trigger(pa, FullyParsed(handle.clone()));
+
+    Some(())
+}
+
+/// Does a forced parsing of the handle, returning the [`BufferParts`]
+///
+/// This is used when the [`Tags`] of the [`Buffer`] need to be
+/// changed outside of the [`BufferUpdated`] hook.
+///
+/// [`Tags`]: duat_core::text::Tags
+pub(crate) fn sync_parse_mut<'p>(
+    pa: &'p mut Pass,
+    handle: &'p Handle,
+) -> Option<(&'p mut Parser, BufferParts<'p>)> {
+    let printed_lines = printed_line_ranges(pa, handle);
+    let visible_ranges = get_visible_ranges(&printed_lines);
+    update_parser(pa, handle, &visible_ranges)?;
+
+    let (parser, buffer) = PARSERS.write(pa, handle)?;
+    let mut parts = TRACKER.parts(buffer).unwrap();
     parser.parse(&mut parts, &visible_ranges, None, handle);
 
-    Some((parser, buffer))
+    Some((parser, parts))
 }
 
 /// The Key for tree-sitter
@@ -762,19 +2201,41 @@ fn input_edit(change: Change<&str>, bytes: &Bytes) -> InputEdit {
 /// Spent too long parsing, yield if necessary
 fn must_yield(start: Option<Instant>) -> bool {
     if let Some(start) = start {
-        start.elapsed() >= PARSE_TIMEOUT && duat_core::context::has_unhandled_events()
+        start.elapsed() >= options().parse_budget && duat_core::context::has_unhandled_events()
     } else {
         false
     }
 }
 
+/// The byte ranges of the printed lines of every view of a buffer
+///
+/// A buffer shown in more than one window or split prints different
+/// lines in each, and all of them should be parsed and highlighted.
+fn printed_line_ranges(pa: &Pass, handle: &Handle) -> Vec<Range<usize>> {
+    let mut printed_lines: Vec<Range<usize>> = context::buffers(pa)
+        .into_iter()
+        .filter(|other| other == handle)
+        .flat_map(|other| other.printed_line_ranges(pa))
+        .collect();
+
+    // The Handle may not be in any window yet.
+    if printed_lines.is_empty() {
+        printed_lines = handle.printed_line_ranges(pa);
+    }
+
+    printed_lines.sort_unstable_by_key(|range| (range.start, range.end));
+    printed_lines.dedup();
+    printed_lines
+}
+
 fn get_visible_ranges(printed_lines: &[Range<usize>]) -> Vec<Range<usize>> {
     let mut ranges_to_parse: Vec<Range<usize>> = Vec::new();
     for range in printed_lines {
+        // Views of the same Buffer may show some of the same lines.
         if let Some(last) = ranges_to_parse.last_mut()
-            && last.end == range.start
+            && last.end >= range.start
         {
-            last.end = range.end
+            last.end = last.end.max(range.end)
         } else {
             ranges_to_parse.push(range.clone())
         }
@@ -819,7 +2280,7 @@ fn descendant_in(node: Node, line: usize, column: usize) -> Node {
     node.descendant_for_point_range(start, end).unwrap()
 }
 
-fn parser_fn<'a>(bytes: &'a Bytes) -> impl FnMut(usize, TsPoint) -> &'a [u8] {
+pub(crate) fn parser_fn<'a>(bytes: &'a Bytes) -> impl FnMut(usize, TsPoint) -> &'a [u8] {
     let [s0, s1] = bytes.slices(..).to_array();
     |byte, _point| {
         if byte < s0.len() {
@@ -830,15 +2291,27 @@ fn parser_fn<'a>(bytes: &'a Bytes) -> impl FnMut(usize, TsPoint) -> &'a [u8] {
     }
 }
 
+/// The [`SemanticForms`] of a parsed [`TsTree`]
+///
+/// This is called from the worker thread as well.
+pub(crate) fn semantic_forms_of(
+    (lang, _, Queries { highlights, locals, .. }): LangParts<'static>,
+    forms: &[(FormId, u8)],
+    ts_tree: &TsTree,
+    bytes: &Bytes,
+) -> SemanticForms {
+    let root = ts_tree.root_node();
+    Locals::new(lang, locals, root, bytes).semantic_forms(highlights, forms, root, bytes)
+}
+
+/// Above the priority of any form from [`forms_from_lang_parts`]
+pub(crate) fn semantic_priority() -> u8 {
+    options().priorities.len().min(MAX_PRIORITY) as u8 + 1
+}
+
 fn forms_from_lang_parts(
     (lang, _, Queries { highlights, .. }): LangParts<'static>,
 ) -> &'static [(FormId, u8)] {
-    #[rustfmt::skip]
-    const PRIORITIES: &[&str] = &[
-        "markup", "operator", "comment", "string", "diff", "variable", "module", "label",
-        "character", "boolean", "number", "type", "attribute", "property", "function", "constant",
-        "constructor", "keyword", "punctuation",
-    ];
     type MemoizedForms<'a> = HashMap<&'a str, &'a [(FormId, u8)]>;
 
     static LISTS: LazyLock<Mutex<MemoizedForms<'static>>> = LazyLock::new(Mutex::default);
@@ -849,10 +2322,12 @@ fn forms_from_lang_parts(
     } else {
         let capture_names = highlights.capture_names();
         let priorities = capture_names.iter().map(|name| {
-            PRIORITIES
+            options()
+                .priorities
                 .iter()
-                .take_while(|p| !name.starts_with(*p))
-                .count() as u8
+                .take_while(|p| !name.starts_with(p.as_str()))
+                .count()
+                .min(MAX_PRIORITY) as u8
         });
 
         let ids = form::ids_of_non_static(
@@ -868,7 +2343,7 @@ fn forms_from_lang_parts(
 }
 
 #[derive(Clone, Copy)]
-struct TsBuf<'a>(&'a Bytes);
+pub(crate) struct TsBuf<'a>(pub &'a Bytes);
 
 impl<'a> TextProvider<&'a [u8]> for TsBuf<'a> {
     type I = std::array::IntoIter<&'a [u8], 2>;
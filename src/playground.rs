@@ -0,0 +1,170 @@
+use std::{
+    ops::Range,
+    sync::{LazyLock, Mutex},
+};
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    form,
+    hook::{self, BufferUpdated},
+    text::{Tagger, TextState, txt},
+    ui::Widget,
+};
+use tree_sitter::Query;
+
+use crate::parser;
+
+static PLAYGROUNDS: LazyLock<Mutex<Vec<Playground>>> = LazyLock::new(Mutex::default);
+
+/// Adds the command used for opening a query playground
+///
+/// This also adds the hook that compiles the queries of
+/// playgrounds, and highlights their matches on the source buffers.
+pub(crate) fn add_playground_command() {
+    cmd::add("query-playground", |pa: &mut Pass| -> CmdResult {
+        let source = context::current_buffer(pa);
+        if parser::sync_parse(pa, &source).is_none() {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        }
+
+        let name = source.read(pa).name().replace(['/', '\\', ' '], "_");
+        let path = std::env::temp_dir()
+            .join("duat-treesitter")
+            .join(format!("{name}.scm"));
+
+        cmd::call(pa, format!("edit {}", path.display()))?;
+
+        let query_buffer = context::current_buffer(pa);
+        if query_buffer == source {
+            return Err(txt!("Failed to open the query playground"));
+        }
+
+        let query_state = query_buffer.read(pa).text().text_state();
+
+        let mut playgrounds = PLAYGROUNDS.lock().unwrap();
+        playgrounds.retain(|playground| playground.query_buffer != query_buffer);
+        playgrounds.push(Playground {
+            query_buffer: query_buffer.clone(),
+            source: source.clone(),
+            query_state: Some(query_state),
+            source_state: None,
+        });
+        drop(playgrounds);
+
+        run_playground(pa, &query_buffer, &source);
+
+        Ok(Some(txt!("Opened query playground for [buffer]{name}")))
+    })
+    .doc(
+        txt!("Opens a query buffer whose matches are highlighted on the current buffer"),
+        Some(txt!(
+            "The query is compiled against the language of the current buffer whenever either \
+             of them changes"
+        )),
+    );
+
+    hook::add::<BufferUpdated>(|pa, handle| {
+        let mut to_run = Vec::new();
+
+        PLAYGROUNDS.lock().unwrap().retain_mut(|playground| {
+            if playground.query_buffer.is_closed(pa) || playground.source.is_closed(pa) {
+                if !playground.source.is_closed(pa) {
+                    to_run.push((playground.query_buffer.clone(), playground.source.clone()));
+                }
+                return false;
+            }
+
+            // Moving carets or tagging also updates the Buffers, so only the
+            // changes to their bytes are taken into account.
+            let (last_state, buffer) = if *handle == playground.query_buffer {
+                (&mut playground.query_state, &playground.query_buffer)
+            } else if *handle == playground.source {
+                (&mut playground.source_state, &playground.source)
+            } else {
+                return true;
+            };
+
+            let state = buffer.read(pa).text().text_state();
+            if last_state.is_none_or(|last| state.bytes_have_changed_since(last)) {
+                *last_state = Some(state);
+                to_run.push((playground.query_buffer.clone(), playground.source.clone()));
+            }
+
+            true
+        });
+
+        for (query_buffer, source) in to_run {
+            run_playground(pa, &query_buffer, &source);
+        }
+    });
+}
+
+/// A query [`Buffer`], tied to a source `Buffer`
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+struct Playground {
+    query_buffer: Handle,
+    source: Handle,
+    query_state: Option<TextState>,
+    source_state: Option<TextState>,
+}
+
+/// Compiles the query of a playground and highlights its matches
+///
+/// If the query [`Buffer`] was closed, the highlighting is removed.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+fn run_playground(pa: &mut Pass, query_buffer: &Handle, source: &Handle) {
+    let tagger = playground_tagger();
+
+    if query_buffer.is_closed(pa) {
+        source.text_mut(pa).remove_tags(tagger, ..);
+        return;
+    }
+
+    let query_src = query_buffer.read(pa).text().to_string();
+
+    let Some((parser, buffer)) = parser::sync_parse(pa, source) else {
+        return;
+    };
+
+    let query = match Query::new(parser.language(), &query_src) {
+        Ok(query) => query,
+        Err(err) => {
+            context::error!(
+                "Query error at [a]{}:{}[]: {}",
+                err.row + 1,
+                err.column + 1,
+                err.message
+            );
+            return;
+        }
+    };
+
+    let bytes = buffer.bytes();
+    let captures: Vec<(Range<usize>, &str)> = parser
+        .query_captures(&query, 0..bytes.len().byte(), bytes)
+        .into_iter()
+        .map(|(range, index)| (range, query.capture_names()[index as usize]))
+        .collect();
+
+    let match_form = form::id_of!("match.QueryPlayground");
+    // Above the priority of any form from highlights or locals.
+    let priority = parser::semantic_priority() + 1;
+    let mut text = source.text_mut(pa);
+    text.remove_tags(tagger, ..);
+
+    for (range, name) in captures {
+        let form = form::id_of_non_static(name);
+        text.insert_tag(tagger, range.clone(), match_form.to_tag(priority));
+        text.insert_tag(tagger, range, form.to_tag(priority + 1));
+    }
+}
+
+/// The [`Tagger`] for query playgrounds
+fn playground_tagger() -> Tagger {
+    static TAGGER: LazyLock<Tagger> = Tagger::new_static();
+    *TAGGER
+}
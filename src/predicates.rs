@@ -0,0 +1,575 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{LazyLock, Mutex},
+};
+
+use duat_core::{context, text::Bytes};
+use regex::Regex;
+use tree_sitter::{Node, Point as TsPoint, Query, QueryCapture, QueryMatch, QueryPredicateArg};
+
+use crate::locals::Locals;
+
+/// Directives that are applied by the consumers of a [`Query`]
+const DIRECTIVES: &[&str] = &[
+    "offset!",
+    "trim!",
+    "downcase!",
+    "gsub!",
+    "set-lang-from-info-string!",
+    "set-lang-from-mimetype!",
+];
+
+/// Wether a [`QueryMatch`] satisfies the predicates that aren't
+/// evaluated by tree-sitter itself
+///
+/// These are `#has-ancestor?`, `#has-parent?`, `#kind-eq?` and
+/// their `not-` forms, as well as `#is?` and `#is-not?`. The
+/// latter can only check for `local`, which needs the [`Locals`] of
+/// the tree, and nodes are considered not local without them.
+///
+/// Unknown predicates are accepted, but a warning is sent the first
+/// time that each of them is found.
+pub(crate) fn satisfies_predicates(
+    query: &Query,
+    qm: &QueryMatch,
+    locals: Option<&Locals>,
+) -> bool {
+    let nodes_of = |index: u32| {
+        qm.captures
+            .iter()
+            .filter(move |cap| cap.index == index)
+            .map(|cap| cap.node)
+    };
+
+    let general = query
+        .general_predicates(qm.pattern_index)
+        .iter()
+        .all(|pred| {
+            if DIRECTIVES.contains(&pred.operator.as_ref()) {
+                return true;
+            }
+
+            let (is_negated, name) = match pred.operator.strip_prefix("not-") {
+                Some(name) => (true, name),
+                None => (false, pred.operator.as_ref()),
+            };
+
+            let [QueryPredicateArg::Capture(index), args @ ..] = pred.args.as_ref() else {
+                warn_unknown(&pred.operator);
+                return true;
+            };
+
+            let is_of_kind = |node: Node| {
+                args.iter().any(|arg| match arg {
+                    QueryPredicateArg::String(kind) => node.kind() == kind.as_ref(),
+                    QueryPredicateArg::Capture(_) => false,
+                })
+            };
+
+            // Optional captures that weren't captured don't affect the match.
+            let mut nodes = nodes_of(*index).peekable();
+            if nodes.peek().is_none() {
+                return true;
+            }
+
+            let holds = match name {
+                "has-ancestor?" => nodes
+                    .any(|node| std::iter::successors(node.parent(), Node::parent).any(is_of_kind)),
+                "has-parent?" => nodes.any(|node| node.parent().is_some_and(is_of_kind)),
+                "kind-eq?" => nodes.any(is_of_kind),
+                _ => {
+                    warn_unknown(&pred.operator);
+                    return true;
+                }
+            };
+
+            holds != is_negated
+        });
+
+    general
+        && query
+            .property_predicates(qm.pattern_index)
+            .iter()
+            .all(|(prop, is_positive)| {
+                if prop.key.as_ref() != "local" {
+                    warn_unknown(&format!("is? {}", prop.key));
+                    return true;
+                }
+
+                let is_local =
+                    |node: Node| locals.is_some_and(|locals| locals.is_local(node.byte_range()));
+                let holds = match prop.capture_id {
+                    Some(index) => nodes_of(index as u32).any(is_local),
+                    None => qm.captures.iter().any(|cap| is_local(cap.node)),
+                };
+
+                holds == *is_positive
+            })
+}
+
+/// Sends a warning about an unknown predicate, once
+fn warn_unknown(predicate: &str) {
+    static WARNED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
+
+    if WARNED.lock().unwrap().insert(predicate.to_string()) {
+        context::warn!("Tree-sitter predicate [a]#{predicate}[] is not supported");
+    }
+}
+
+/// The byte [`Range`] of a [`QueryCapture`], with `#offset!` and
+/// `#trim!` applied
+///
+/// The `#offset!` directive takes the form of
+/// `(#offset! @capture start_row start_col end_row end_col)`, where
+/// each value is added to the corresponding position of the node.
+///
+/// The `#trim!` directive takes the form of
+/// `(#trim! @capture start_lines start_cols end_lines end_cols)`,
+/// where each value is either `1` or `0`. If only the capture is
+/// given, blank lines are trimmed from the end.
+pub(crate) fn capture_range(
+    query: &Query,
+    pattern_index: usize,
+    cap: &QueryCapture,
+    bytes: &Bytes,
+) -> Range<usize> {
+    let range = offset_range(query, pattern_index, cap, bytes);
+
+    let Some(trim) = query
+        .general_predicates(pattern_index)
+        .iter()
+        .filter(|pred| pred.operator.as_ref() == "trim!")
+        .find_map(|pred| match pred.args.as_ref() {
+            [QueryPredicateArg::Capture(index), args @ ..] if *index == cap.index => {
+                let mut flags = args.iter().map(|arg| match arg {
+                    QueryPredicateArg::String(str) => str.as_ref() == "1",
+                    QueryPredicateArg::Capture(_) => false,
+                });
+                Some(match args.len() {
+                    0 => [false, false, true, false],
+                    _ => [0; 4].map(|_| flags.next().unwrap_or(false)),
+                })
+            }
+            _ => None,
+        })
+    else {
+        return range;
+    };
+
+    trim_range(range, trim, bytes)
+}
+
+/// The text of a [`QueryCapture`], with `#downcase!` and `#gsub!`
+/// applied
+///
+/// The `#gsub!` directive takes the form of
+/// `(#gsub! @capture pattern replacement)`, where `pattern` is a Lua
+/// pattern. The directives are applied in the order that they show
+/// up in the pattern.
+pub(crate) fn capture_text(
+    query: &Query,
+    qm: &QueryMatch,
+    cap: &QueryCapture,
+    bytes: &Bytes,
+) -> String {
+    let mut text = bytes[cap.node.byte_range()].to_string();
+
+    for pred in query.general_predicates(qm.pattern_index) {
+        let [QueryPredicateArg::Capture(index), args @ ..] = pred.args.as_ref() else {
+            continue;
+        };
+
+        if *index != cap.index {
+            continue;
+        }
+
+        match (pred.operator.as_ref(), args) {
+            ("downcase!", []) => text = text.to_lowercase(),
+            (
+                "gsub!",
+                [
+                    QueryPredicateArg::String(pattern),
+                    QueryPredicateArg::String(replacement),
+                ],
+            ) => match lua_pattern_regex(pattern) {
+                Some(regex) => {
+                    let replacement = lua_replacement(replacement);
+                    text = regex.replace_all(&text, replacement.as_str()).into_owned();
+                }
+                None => warn_unknown(&format!("gsub! {pattern}")),
+            },
+            _ => {}
+        }
+    }
+
+    text
+}
+
+/// The byte [`Range`] of a [`QueryCapture`], with `#offset!` applied
+fn offset_range(
+    query: &Query,
+    pattern_index: usize,
+    cap: &QueryCapture,
+    bytes: &Bytes,
+) -> Range<usize> {
+    let Some([start_row, start_col, end_row, end_col]) = offset_of(query, pattern_index, cap.index)
+    else {
+        return cap.node.byte_range();
+    };
+
+    let node = cap.node;
+    let start = offset_byte(
+        node.start_byte(),
+        node.start_position(),
+        [start_row, start_col],
+        bytes,
+    );
+    let end = offset_byte(
+        node.end_byte(),
+        node.end_position(),
+        [end_row, end_col],
+        bytes,
+    );
+
+    start..end.max(start)
+}
+
+/// The row offsets of a [`QueryCapture`] from `#offset!`
+///
+/// Returns `(0, 0)` if there is no `#offset!` for the capture.
+pub(crate) fn row_offsets(query: &Query, pattern_index: usize, cap: &QueryCapture) -> (i32, i32) {
+    offset_of(query, pattern_index, cap.index)
+        .map(|[start_row, _, end_row, _]| (start_row, end_row))
+        .unwrap_or_default()
+}
+
+/// The arguments of an `#offset!` directive for a capture
+fn offset_of(query: &Query, pattern_index: usize, capture_index: u32) -> Option<[i32; 4]> {
+    query
+        .general_predicates(pattern_index)
+        .iter()
+        .filter(|pred| pred.operator.as_ref() == "offset!")
+        .find_map(|pred| {
+            let [QueryPredicateArg::Capture(index), offsets @ ..] = pred.args.as_ref() else {
+                return None;
+            };
+
+            if *index != capture_index {
+                return None;
+            }
+
+            let mut values = offsets.iter().map(|arg| match arg {
+                QueryPredicateArg::String(str) => str.parse::<i32>().ok(),
+                QueryPredicateArg::Capture(_) => None,
+            });
+
+            Some([0; 4].map(|_| values.next().flatten().unwrap_or(0)))
+        })
+}
+
+/// A byte, moved by some rows and columns
+///
+/// If the byte is moved to another row, the result is clamped to
+/// the line that it lands on.
+fn offset_byte(byte: usize, point: TsPoint, [rows, cols]: [i32; 2], bytes: &Bytes) -> usize {
+    if rows == 0 {
+        return (byte as i32 + cols).clamp(0, bytes.len().byte() as i32) as usize;
+    }
+
+    let last_line = bytes.len().line().saturating_sub(1);
+    let row = (point.row as i32 + rows).clamp(0, last_line as i32) as usize;
+
+    let line = bytes.line(row).byte_range();
+    let byte = line.start as i32 + point.column as i32 + cols;
+
+    (byte.max(line.start as i32) as usize).min(line.end)
+}
+
+/// Trims whitespace from a [`Range`], linewise and/or charwise
+fn trim_range(
+    range: Range<usize>,
+    [start_lines, start_cols, end_lines, end_cols]: [bool; 4],
+    bytes: &Bytes,
+) -> Range<usize> {
+    let text = bytes[range.clone()].to_string();
+    if text.trim().is_empty() {
+        return range;
+    }
+
+    let first = text.len() - text.trim_start().len();
+    let last = text.trim_end().len();
+
+    let start = if start_cols {
+        first
+    } else if start_lines {
+        text[..first].rfind('\n').map(|i| i + 1).unwrap_or(0)
+    } else {
+        0
+    };
+
+    let end = if end_cols {
+        last
+    } else if end_lines {
+        last + text[last..].find('\n').unwrap_or(text.len() - last)
+    } else {
+        text.len()
+    };
+
+    range.start + start..range.start + end
+}
+
+/// A [`Regex`] from a Lua pattern, as used by nvim-treesitter
+///
+/// Returns [`None`] if the pattern uses features that have no
+/// equivalent, like `%b` and `%f`.
+fn lua_pattern_regex(pattern: &str) -> Option<Regex> {
+    static REGEXES: LazyLock<Mutex<HashMap<String, Option<Regex>>>> = LazyLock::new(Mutex::default);
+
+    let mut regexes = REGEXES.lock().unwrap();
+    if let Some(regex) = regexes.get(pattern) {
+        return regex.clone();
+    }
+
+    let class = |char: char| {
+        Some(match char.to_ascii_lowercase() {
+            'a' => "alpha",
+            'c' => "cntrl",
+            'd' => "digit",
+            'l' => "lower",
+            'p' => "punct",
+            's' => "space",
+            'u' => "upper",
+            'w' => "alnum",
+            'x' => "xdigit",
+            _ => return None,
+        })
+    };
+    let negation = |char: char| if char.is_ascii_uppercase() { "^" } else { "" };
+
+    let translate = || -> Option<String> {
+        let mut regex = String::from("(?s)");
+        let mut chars = pattern.chars().peekable();
+        let mut follows_item = false;
+
+        if chars.next_if_eq(&'^').is_some() {
+            regex.push('^');
+        }
+
+        while let Some(char) = chars.next() {
+            let is_item = match char {
+                '%' => {
+                    let escaped = chars.next()?;
+                    if let Some(class) = class(escaped) {
+                        regex.push_str(&format!("[[:{}{class}:]]", negation(escaped)));
+                    } else if escaped.is_ascii_alphanumeric() {
+                        return None;
+                    } else {
+                        regex.push_str(&regex::escape(&escaped.to_string()));
+                    }
+                    true
+                }
+                '[' => {
+                    regex.push('[');
+                    if chars.next_if_eq(&'^').is_some() {
+                        regex.push('^');
+                    }
+                    if chars.next_if_eq(&']').is_some() {
+                        regex.push_str("\\]");
+                    }
+
+                    loop {
+                        match chars.next()? {
+                            ']' => break,
+                            '%' => {
+                                let escaped = chars.next()?;
+                                if let Some(class) = class(escaped) {
+                                    regex.push_str(&format!("[:{}{class}:]", negation(escaped)));
+                                } else {
+                                    regex.push('\\');
+                                    regex.push(escaped);
+                                }
+                            }
+                            char @ ('[' | '&' | '~' | '\\') => {
+                                regex.push('\\');
+                                regex.push(char);
+                            }
+                            char => regex.push(char),
+                        }
+                    }
+
+                    regex.push(']');
+                    true
+                }
+                '*' | '+' | '?' if follows_item => {
+                    regex.push(char);
+                    false
+                }
+                '-' if follows_item => {
+                    regex.push_str("*?");
+                    false
+                }
+                '$' if chars.peek().is_none() => {
+                    regex.push('$');
+                    false
+                }
+                '.' => {
+                    regex.push('.');
+                    true
+                }
+                '(' => {
+                    if chars.peek() == Some(&')') {
+                        return None;
+                    }
+                    regex.push('(');
+                    false
+                }
+                ')' => {
+                    regex.push(')');
+                    true
+                }
+                char => {
+                    regex.push_str(&regex::escape(&char.to_string()));
+                    true
+                }
+            };
+
+            follows_item = is_item;
+        }
+
+        Some(regex)
+    };
+
+    let regex = translate().and_then(|regex| Regex::new(&regex).ok());
+    regexes.insert(pattern.to_string(), regex.clone());
+
+    regex
+}
+
+/// A [`Regex`] replacement string from a Lua one
+fn lua_replacement(replacement: &str) -> String {
+    let mut new = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '%' => match chars.next() {
+                Some(digit @ '0'..='9') => new.push_str(&format!("${{{digit}}}")),
+                Some(char) => new.push(char),
+                None => {}
+            },
+            '$' => new.push_str("$$"),
+            char => new.push(char),
+        }
+    }
+
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'h>(pattern: &str, haystack: &'h str) -> Option<&'h str> {
+        let regex = lua_pattern_regex(pattern).unwrap();
+        regex.find(haystack).map(|found| found.as_str())
+    }
+
+    fn gsub(pattern: &str, replacement: &str, haystack: &str) -> String {
+        let regex = lua_pattern_regex(pattern).unwrap();
+        regex
+            .replace_all(haystack, lua_replacement(replacement))
+            .into_owned()
+    }
+
+    #[test]
+    fn lua_classes() {
+        assert_eq!(find("%d+", "abc123def"), Some("123"));
+        assert_eq!(find("%a+", "123abc456"), Some("abc"));
+        assert_eq!(find("%S+", "  word  "), Some("word"));
+        assert_eq!(find("[%d_]+", "ab1_2cd"), Some("1_2"));
+        assert_eq!(find("[^%s]+", "  word  "), Some("word"));
+        assert_eq!(find("%.", "a.b"), Some("."));
+        assert!(lua_pattern_regex("%b()").is_none());
+        assert!(lua_pattern_regex("%f[%w]").is_none());
+    }
+
+    #[test]
+    fn lua_lazy_quantifier() {
+        assert_eq!(find("a.-b", "axxbyyb"), Some("axxb"));
+        assert_eq!(find("a.*b", "axxbyyb"), Some("axxbyyb"));
+        // A `-` that doesn't follow an item is just a character.
+        assert_eq!(find("-x", "a-x"), Some("-x"));
+    }
+
+    #[test]
+    fn lua_anchors() {
+        assert_eq!(find("^foo$", "foo"), Some("foo"));
+        assert_eq!(find("^foo$", "foox"), None);
+        assert_eq!(find("^foo$", "xfoo"), None);
+        // Anchors are only special at the edges of the pattern.
+        assert_eq!(find("a$b", "a$b"), Some("a$b"));
+        assert_eq!(find("a^b", "a^b"), Some("a^b"));
+    }
+
+    #[test]
+    fn lua_captures() {
+        assert_eq!(gsub("(%w+)=(%w+)", "%2=%1", "a=b, c=d"), "b=a, d=c");
+        assert_eq!(gsub("%w+", "<%0>", "a b"), "<a> <b>");
+        assert!(lua_pattern_regex("()").is_none());
+    }
+
+    #[test]
+    fn lua_escaped_percent() {
+        assert_eq!(find("100%%", "it is 100%"), Some("100%"));
+        assert_eq!(gsub("x", "%%", "axb"), "a%b");
+        // `$` has no special meaning in Lua replacements.
+        assert_eq!(gsub("(x)", "$1", "axb"), "a$1b");
+    }
+
+    fn bytes(text: &str) -> Bytes {
+        Bytes::from(text.to_string())
+    }
+
+    #[test]
+    fn offsets_within_a_row() {
+        let bytes = bytes("abc\ndef\nghi\n");
+        let point = TsPoint::new(0, 1);
+
+        assert_eq!(offset_byte(1, point, [0, 2], &bytes), 3);
+        assert_eq!(offset_byte(1, point, [0, -3], &bytes), 0);
+        assert_eq!(offset_byte(10, TsPoint::new(2, 2), [0, 100], &bytes), 12);
+    }
+
+    #[test]
+    fn offsets_across_rows() {
+        let bytes = bytes("abc\ndef\nghi\n");
+
+        assert_eq!(offset_byte(1, TsPoint::new(0, 1), [1, 1], &bytes), 6);
+        // Offsets are clamped to the line that they land on.
+        assert_eq!(offset_byte(6, TsPoint::new(1, 2), [-1, 5], &bytes), 4);
+        assert_eq!(offset_byte(6, TsPoint::new(1, 2), [-5, -10], &bytes), 0);
+        assert_eq!(offset_byte(1, TsPoint::new(0, 1), [10, 0], &bytes), 9);
+    }
+
+    #[test]
+    fn trimming() {
+        let text = "x  \n  foo bar  \n  ";
+        let bytes = bytes(text);
+        let range = 1..text.len();
+
+        let trimmed = |trim| &text[trim_range(range.clone(), trim, &bytes)];
+        assert_eq!(trimmed([false, true, false, true]), "foo bar");
+        assert_eq!(trimmed([true, false, true, false]), "  foo bar  ");
+        assert_eq!(trimmed([true, false, false, false]), "  foo bar  \n  ");
+        assert_eq!(trimmed([false, false, false, true]), "  \n  foo bar");
+    }
+
+    #[test]
+    fn trimming_only_whitespace() {
+        let bytes = bytes("x  \n \t y");
+
+        assert_eq!(trim_range(1..6, [true; 4], &bytes), 1..6);
+        assert_eq!(trim_range(1..1, [true; 4], &bytes), 1..1);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use duat_core::text::{Text, txt};
+use tree_sitter::{Language, Query};
+
+/// A capture from a user [`Query`], as returned by
+/// [`TsHandle::ts_query`]
+///
+/// [`TsHandle::ts_query`]: crate::TsHandle::ts_query
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    pub(crate) range: Range<usize>,
+    pub(crate) name: String,
+    pub(crate) lang: &'static str,
+    pub(crate) layer: usize,
+}
+
+impl Capture {
+    /// The byte [`Range`] of the capture, with directives applied
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// The name of the capture, without the `@`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The language of the tree that the capture came from
+    pub fn lang(&self) -> &'static str {
+        self.lang
+    }
+
+    /// How deeply nested the injection of the capture is
+    ///
+    /// This is `0` for captures of the main language of the
+    /// [`Buffer`], `1` for those of its injections, and so on.
+    ///
+    /// [`Buffer`]: duat_core::buffer::Buffer
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+}
+
+/// How many compiled queries are kept in the cache
+const CACHED_QUERIES: usize = 32;
+
+/// A compiled [`Query`] for a language, from its source
+///
+/// The most recently used queries are cached, so compiling the same
+/// source twice is cheap. This also applies to sources that failed
+/// to compile.
+pub(crate) fn cached_query(
+    lang: &'static str,
+    language: &Language,
+    query_src: &str,
+) -> Result<Arc<Query>, Text> {
+    type CachedQuery = ((&'static str, String), Result<Arc<Query>, String>);
+    static QUERIES: Mutex<Vec<CachedQuery>> = Mutex::new(Vec::new());
+
+    let mut queries = QUERIES.lock().unwrap();
+
+    // The most recently used query is kept at the end.
+    let query = match queries
+        .iter()
+        .position(|((l, src), _)| *l == lang && src == query_src)
+    {
+        Some(i) => queries.remove(i).1,
+        None => match Query::new(language, query_src) {
+            Ok(query) => Ok(Arc::new(query)),
+            Err(err) => Err(format!(
+                "{}:{}: {}",
+                err.row + 1,
+                err.column + 1,
+                err.message
+            )),
+        },
+    };
+
+    if queries.len() == CACHED_QUERIES {
+        _ = queries.remove(0);
+    }
+    queries.push(((lang, query_src.to_string()), query.clone()));
+
+    query.map_err(|err| txt!("Query error at {err}"))
+}
@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    text::{Bytes, txt},
+};
+
+use crate::{Capture, TsHandle};
+
+/// Adds the command used for structural search and replace
+pub(crate) fn add_replace_command() {
+    cmd::add(
+        "ts-replace",
+        |pa: &mut Pass, query: String, template: String| -> CmdResult {
+            let handle = context::current_buffer(pa);
+
+            let main = handle.selections(pa).main();
+            let range = if main.anchor().is_some() {
+                main.byte_range(handle.read(pa).bytes())
+            } else {
+                0..handle.read(pa).bytes().len().byte()
+            };
+
+            match handle.ts_replace(pa, &query, &template, range)? {
+                0 => Err(txt!("No matches to replace")),
+                1 => Ok(Some(txt!("Replaced [a]1[] match"))),
+                n => Ok(Some(txt!("Replaced [a]{n}[] matches"))),
+            }
+        },
+    )
+    .doc(
+        txt!("Replaces every match of a query with a template"),
+        Some(txt!(
+            "The replaced range is that of the [a]@match[] capture, or that of all captures, if \
+             there is no [a]@match[]. In the template, [a]@name[] or [a]@{{name}}[] are \
+             replaced by the text of a capture, and [a]@@[] by a literal [a]@[]. If the main \
+             selection isn't empty, only the matches within it are replaced"
+        )),
+    )
+    .doc_param(txt!("The query to match"), None, None)
+    .doc_param(txt!("The template to replace the matches with"), None, None);
+}
+
+/// The replacements for a list of matches, within a [`Range`]
+///
+/// Matches that overlap with earlier ones are left out, so the
+/// outermost match is the one replaced.
+pub(crate) fn replacements(
+    matches: Vec<Vec<Capture>>,
+    template: &str,
+    range: Range<usize>,
+    bytes: &Bytes,
+) -> Vec<(Range<usize>, String)> {
+    let mut replacements: Vec<(Range<usize>, String)> = matches
+        .into_iter()
+        .filter_map(|captures| {
+            let target = captures
+                .iter()
+                .find(|cap| cap.name() == "match")
+                .map(Capture::range)
+                .or_else(|| captures.iter().map(Capture::range).reduce(union))?;
+
+            (range.start <= target.start && target.end <= range.end)
+                .then(|| (target, expand_template(template, &captures, bytes)))
+        })
+        .collect();
+
+    replacements.sort_unstable_by(|(lhs, _), (rhs, _)| {
+        lhs.start.cmp(&rhs.start).then(rhs.end.cmp(&lhs.end))
+    });
+
+    let mut last_end = 0;
+    replacements.retain(|(range, _)| {
+        let keep = range.start >= last_end;
+        if keep {
+            last_end = range.end;
+        }
+        keep
+    });
+
+    replacements
+}
+
+/// Applies replacements to a [`Buffer`], as a single change
+///
+/// Returns the number of replacements.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+pub(crate) fn apply_replacements(
+    handle: &Handle,
+    pa: &mut Pass,
+    replacements: Vec<(Range<usize>, String)>,
+) -> usize {
+    let mut text = handle.text_mut(pa);
+    text.new_moment();
+
+    // Replacing from the end keeps the earlier ranges valid.
+    for (range, edit) in replacements.iter().rev() {
+        text.replace_range(range.clone(), edit);
+    }
+
+    text.new_moment();
+    replacements.len()
+}
+
+/// Expands the capture references in a template
+///
+/// A reference is either `@name`, for names made of alphanumeric
+/// characters and `_`, or `@{name}`, for any name. Captures that
+/// weren't part of the match are replaced by nothing.
+fn expand_template(template: &str, captures: &[Capture], bytes: &Bytes) -> String {
+    let text_of = |name: &str| {
+        captures
+            .iter()
+            .filter(|cap| cap.name() == name)
+            .map(Capture::range)
+            .reduce(union)
+            .map(|range| bytes[range].to_string())
+            .unwrap_or_default()
+    };
+
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(i) = rest.find('@') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        if let Some(after) = rest.strip_prefix('@') {
+            expanded.push('@');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{')
+            && let Some(end) = after.find('}')
+        {
+            expanded.push_str(&text_of(&after[..end]));
+            rest = &after[end + 1..];
+        } else {
+            let end = rest
+                .find(|char: char| !(char.is_alphanumeric() || char == '_'))
+                .unwrap_or(rest.len());
+
+            if end == 0 {
+                expanded.push('@');
+            } else {
+                expanded.push_str(&text_of(&rest[..end]));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn union(lhs: Range<usize>, rhs: Range<usize>) -> Range<usize> {
+    lhs.start.min(rhs.start)..lhs.end.max(rhs.end)
+}
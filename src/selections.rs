@@ -0,0 +1,102 @@
+use std::ops::Range;
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    text::txt,
+};
+
+use crate::TsHandle;
+
+/// Adds the commands used for selecting syntax nodes
+pub(crate) fn add_selection_commands() {
+    cmd::add("selection-expand", |pa: &mut Pass| -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_expand_selections(pa, ..) {
+            Some(0) => Err(txt!("No nodes around the selections")),
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    })
+    .doc(
+        txt!("Expands each selection to the smallest node around it"),
+        None,
+    );
+    cmd::add("selection-shrink", |pa: &mut Pass| -> CmdResult {
+        let handle = context::current_buffer(pa);
+        match handle.ts_shrink_selections(pa, ..) {
+            Some(0) => Err(txt!("No expanded selections to shrink")),
+            Some(_) => Ok(None),
+            None => Err(txt!("Tree-sitter is not enabled for this buffer")),
+        }
+    })
+    .doc(
+        txt!("Shrinks each selection back to what it was before expanding"),
+        None,
+    );
+}
+
+/// Moves the `n`th selections to new [`Range`]s
+///
+/// Returns the number of selections that were moved.
+pub(crate) fn move_selections(
+    handle: &Handle,
+    pa: &mut Pass,
+    moves: Vec<(usize, Range<usize>)>,
+) -> usize {
+    let mut n = 0;
+    handle.edit_all(pa, |mut c| {
+        if let Some((_, range)) = moves.iter().find(|(i, _)| *i == n) {
+            c.move_to(range.clone());
+        }
+        n += 1;
+    });
+
+    moves.len()
+}
+
+/// The [`Range`]s that each selection had before being expanded
+#[derive(Default, Debug)]
+pub(crate) struct SelectionHistory(Vec<Vec<Range<usize>>>);
+
+impl SelectionHistory {
+    /// Records the expansion of the `n`th selection
+    ///
+    /// If the selection was moved since its last expansion, its
+    /// history is started over.
+    pub fn push(&mut self, n: usize, from: Range<usize>, to: Range<usize>) {
+        if self.0.len() <= n {
+            self.0.resize_with(n + 1, Vec::new);
+        }
+
+        let ranges = &mut self.0[n];
+        if ranges.last() != Some(&from) {
+            ranges.clear();
+            ranges.push(from);
+        }
+
+        ranges.push(to);
+    }
+
+    /// The [`Range`] of the `n`th selection before its last
+    /// expansion
+    ///
+    /// Returns [`None`] if it wasn't expanded, or if it was moved
+    /// since then.
+    pub fn pop(&mut self, n: usize, current: Range<usize>) -> Option<Range<usize>> {
+        let ranges = self.0.get_mut(n)?;
+        if ranges.len() < 2 || ranges.last() != Some(&current) {
+            ranges.clear();
+            return None;
+        }
+
+        ranges.pop();
+        ranges.last().cloned()
+    }
+
+    /// Forgets about all expansions
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use duat_core::text::Bytes;
+use tree_sitter::InputEdit;
+
+/// The length at which the chunks of a [`Snapshot`] are split
+const CHUNK_LEN: usize = 16 * 1024;
+
+/// A copy of the [`Bytes`] of a [`Buffer`], for the worker thread
+///
+/// The bytes are split in chunks, which are shared between copies,
+/// so copying a `Snapshot` only copies the list of chunks. Edits
+/// throw away the chunks that they touch, and only those are copied
+/// from the `Bytes` again.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+#[derive(Clone)]
+pub struct Snapshot {
+    chunks: Arc<Vec<Chunk>>,
+}
+
+impl Snapshot {
+    /// A new `Snapshot`, for [`Bytes`] of a given length
+    ///
+    /// Nothing is copied until [`Snapshot::update`] is called.
+    pub fn new(len: usize) -> Self {
+        Self {
+            chunks: Arc::new(vec![Chunk { start: 0, len, bytes: None }]),
+        }
+    }
+
+    /// Throws away the chunks that an [`InputEdit`] touches
+    pub fn edit(&mut self, edit: &InputEdit) {
+        let chunks = Arc::make_mut(&mut self.chunks);
+        let shift = edit.new_end_byte as isize - edit.old_end_byte as isize;
+
+        let first = chunks
+            .partition_point(|chunk| chunk.end() <= edit.start_byte)
+            .min(chunks.len() - 1);
+        let last = chunks
+            .partition_point(|chunk| chunk.start < edit.old_end_byte)
+            .max(first + 1);
+
+        let start = chunks[first].start;
+        let end = (chunks[last - 1].end() as isize + shift) as usize;
+        chunks.splice(first..last, [Chunk {
+            start,
+            len: end - start,
+            bytes: None,
+        }]);
+
+        for chunk in chunks[first + 1..].iter_mut() {
+            chunk.start = (chunk.start as isize + shift) as usize;
+        }
+    }
+
+    /// Copies the chunks that were thrown away from the [`Bytes`]
+    ///
+    /// Returns a copy of the updated `Snapshot`, which can be sent to
+    /// the worker thread.
+    pub fn update(&mut self, bytes: &Bytes) -> Snapshot {
+        if self.chunks.iter().all(|chunk| chunk.bytes.is_some()) {
+            return self.clone();
+        }
+
+        let chunks = Arc::make_mut(&mut self.chunks);
+        let mut updated = Vec::with_capacity(chunks.len());
+
+        for chunk in chunks.drain(..) {
+            if chunk.bytes.is_some() {
+                updated.push(chunk);
+                continue;
+            }
+
+            let mut start = chunk.start;
+            while start < chunk.end() {
+                // Chunks are split on character boundaries, and small edits
+                // don't split them, so they don't get fragmented.
+                let end = if chunk.end() - start > 2 * CHUNK_LEN {
+                    bytes.point_at_byte(start + CHUNK_LEN).byte()
+                } else {
+                    chunk.end()
+                };
+
+                let copy: Arc<[u8]> = bytes.slices(start..end).to_array().concat().into();
+                updated.push(Chunk {
+                    start,
+                    len: end - start,
+                    bytes: Some(copy),
+                });
+                start = end;
+            }
+        }
+
+        if updated.is_empty() {
+            updated.push(Chunk {
+                start: 0,
+                len: 0,
+                bytes: Some(Arc::from([])),
+            });
+        }
+
+        *chunks = updated;
+        self.clone()
+    }
+
+    /// The bytes from a byte to the end of its chunk
+    ///
+    /// Returns an empty slice past the end of the `Snapshot`.
+    pub fn slice_from(&self, byte: usize) -> &[u8] {
+        let i = self.chunks.partition_point(|chunk| chunk.end() <= byte);
+        match self.chunks.get(i) {
+            Some(Chunk { start, bytes: Some(buf), .. }) => &buf[byte - start..],
+            _ => &[],
+        }
+    }
+
+    /// The [`Bytes`] of this `Snapshot`
+    ///
+    /// This copies every chunk, so it shouldn't be done on the main
+    /// thread.
+    pub fn to_bytes(&self) -> Bytes {
+        let buf: Vec<u8> = self
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.bytes.iter())
+            .flat_map(|bytes| bytes.iter().copied())
+            .collect();
+
+        match String::from_utf8(buf) {
+            Ok(string) => Bytes::from(string),
+            Err(err) => Bytes::from(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        }
+    }
+}
+
+/// A chunk of a [`Snapshot`]
+///
+/// Its bytes are [`None`] if it was edited since they were copied.
+#[derive(Clone)]
+struct Chunk {
+    start: usize,
+    len: usize,
+    bytes: Option<Arc<[u8]>>,
+}
+
+impl Chunk {
+    /// The byte where this `Chunk` ends
+    fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use tree_sitter::Point;
+
+    use super::*;
+
+    /// A 40 KB text, which is split in two chunks
+    fn text() -> String {
+        (0..4000).map(|i| format!("line {i:04}\n")).collect()
+    }
+
+    fn snapshot(text: &str) -> Snapshot {
+        let mut snapshot = Snapshot::new(text.len());
+        snapshot.update(&Bytes::from(text.to_string()));
+        snapshot
+    }
+
+    /// Replaces a [`Range`] of the text, editing the [`Snapshot`]
+    fn replace(snapshot: &mut Snapshot, text: &mut String, range: Range<usize>, new: &str) {
+        snapshot.edit(&InputEdit {
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte: range.start + new.len(),
+            start_position: Point::default(),
+            old_end_position: Point::default(),
+            new_end_position: Point::default(),
+        });
+        text.replace_range(range, new);
+        snapshot.update(&Bytes::from(text.clone()));
+    }
+
+    fn contents(snapshot: &Snapshot) -> String {
+        let mut contents = Vec::new();
+        loop {
+            let slice = snapshot.slice_from(contents.len());
+            if slice.is_empty() {
+                break String::from_utf8(contents).unwrap();
+            }
+            contents.extend_from_slice(slice);
+        }
+    }
+
+    fn chunk(snapshot: &Snapshot, i: usize) -> Arc<[u8]> {
+        snapshot.chunks[i].bytes.clone().unwrap()
+    }
+
+    #[test]
+    fn edits_at_a_chunk_boundary() {
+        let mut text = text();
+        let mut snapshot = snapshot(&text);
+        assert_eq!(snapshot.chunks[1].start, CHUNK_LEN);
+        let first = chunk(&snapshot, 0);
+
+        replace(&mut snapshot, &mut text, CHUNK_LEN..CHUNK_LEN, "inserted\n");
+        assert_eq!(contents(&snapshot), text);
+        assert!(Arc::ptr_eq(&first, &chunk(&snapshot, 0)));
+
+        replace(&mut snapshot, &mut text, CHUNK_LEN - 4..CHUNK_LEN + 4, "");
+        assert_eq!(contents(&snapshot), text);
+
+        let len = text.len();
+        replace(&mut snapshot, &mut text, len..len, "appended\n");
+        assert_eq!(contents(&snapshot), text);
+    }
+
+    #[test]
+    fn edits_that_span_chunks() {
+        let mut text = text();
+        let mut snapshot = snapshot(&text);
+
+        replace(&mut snapshot, &mut text, 10_000..30_000, "replaced\n");
+        assert_eq!(contents(&snapshot), text);
+        assert_eq!(snapshot.chunks.len(), 1);
+
+        replace(&mut snapshot, &mut text, 5..15, &"x".repeat(3 * CHUNK_LEN));
+        assert_eq!(contents(&snapshot), text);
+        assert!(
+            snapshot
+                .chunks
+                .iter()
+                .all(|chunk| chunk.len <= 2 * CHUNK_LEN)
+        );
+    }
+
+    #[test]
+    fn deletions_down_to_empty() {
+        let mut text = text();
+        let mut snapshot = snapshot(&text);
+
+        let len = text.len();
+        replace(&mut snapshot, &mut text, 0..len, "");
+        assert_eq!(contents(&snapshot), "");
+        assert_eq!(snapshot.to_bytes().len().byte(), 0);
+
+        replace(&mut snapshot, &mut text, 0..0, "hello\n");
+        assert_eq!(contents(&snapshot), "hello\n");
+    }
+
+    #[test]
+    fn copies_are_unchanged_by_later_edits() {
+        let mut text = text();
+        let mut snapshot = snapshot(&text);
+        let original = text.clone();
+        let copy = snapshot.clone();
+
+        replace(&mut snapshot, &mut text, 100..200, "");
+        replace(&mut snapshot, &mut text, 20_000..20_000, "inserted\n");
+
+        assert_eq!(contents(&copy), original);
+        assert_eq!(contents(&snapshot), text);
+    }
+}
@@ -3,23 +3,29 @@ use std::ops::Range;
 use duat_core::Ranges;
 use tree_sitter::{InputEdit, Tree as TsTree};
 
+use crate::{locals::SemanticForms, worker::PendingParse};
+
 /// An injected tree, may span multiple [`Range`]s
 #[derive(Debug)]
 pub struct Tree {
     pub region: Ranges,
     pub ts_tree: Option<TsTree>,
     pub needs_parse: bool,
-    pub _combined_pattern_index: Option<usize>,
+    pub pending: Option<PendingParse>,
+    pub semantic: Option<SemanticForms>,
+    pub combined_pattern_index: Option<usize>,
 }
 
 impl Tree {
     /// Returns a new
-    pub fn new(region: Ranges) -> Self {
+    pub fn new(region: Ranges, combined_pattern_index: Option<usize>) -> Self {
         Self {
             region,
             ts_tree: None,
             needs_parse: true,
-            _combined_pattern_index: None,
+            pending: None,
+            semantic: None,
+            combined_pattern_index,
         }
     }
 }
@@ -30,7 +36,10 @@ pub struct Trees(Vec<Tree>);
 impl Trees {
     /// Returns a new [`Regions`]
     pub fn new(regions: impl IntoIterator<Item = Ranges>) -> Self {
-        let mut regions: Vec<_> = regions.into_iter().map(Tree::new).collect();
+        let mut regions: Vec<_> = regions
+            .into_iter()
+            .map(|region| Tree::new(region, None))
+            .collect();
         regions.sort_unstable_by(|lhs, rhs| lhs.region.cmp(&rhs.region));
 
         Self(regions)
@@ -41,6 +50,16 @@ impl Trees {
         self.0.iter()
     }
 
+    /// Returns a mutable [`Iterator`] over the [`Tree`]s
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Tree> {
+        self.0.iter_mut()
+    }
+
+    /// Returns the `n`th [`Tree`] mutably
+    pub fn get_mut(&mut self, n: usize) -> Option<&mut Tree> {
+        self.0.get_mut(n)
+    }
+
     /// Returns an [`Iterator`] over all regions that intersect the
     /// [`Range`] given
     #[track_caller]
@@ -65,8 +84,11 @@ impl Trees {
 
     /// Adds [`Ranges`] to be the `Regions`
     ///
+    /// If `combined_pattern_index` is [`Some`], the [`Tree`] will be
+    /// the one made by combining every match of that pattern.
+    ///
     /// Returns `true` if a new `Ranges` was added.
-    pub fn add_region(&mut self, region: Ranges) -> bool {
+    pub fn add_region(&mut self, region: Ranges, combined_pattern_index: Option<usize>) -> bool {
         let mut add_new = true;
 
         for range in region.iter() {
@@ -91,21 +113,91 @@ impl Trees {
                 .binary_search_by(|tree| tree.region.cmp(&region))
                 .unwrap_err();
 
-            self.0.insert(i, Tree::new(region));
+            self.0.insert(i, Tree::new(region, combined_pattern_index));
             true
         } else {
             false
         }
     }
 
+    /// Sets the region of a combined [`Tree`] within some [`Ranges`]
+    ///
+    /// If the new region is empty, the `Tree` is removed. Otherwise,
+    /// its [`TsTree`] is kept, so adding or removing a fragment
+    /// doesn't require parsing the whole region again.
+    ///
+    /// Returns `true` if the region was changed.
+    pub fn set_combined(&mut self, pattern_index: usize, within: &Ranges, region: Ranges) -> bool {
+        let old = self.0.iter().position(|tree| {
+            tree.combined_pattern_index == Some(pattern_index)
+                && tree
+                    .region
+                    .iter()
+                    .any(|range| within.intersects_with(range))
+        });
+
+        if let Some(i) = old {
+            if self.0[i].region == region {
+                return false;
+            }
+
+            let mut tree = self.0.remove(i);
+            if !region.is_empty() {
+                tree.region = region;
+                tree.needs_parse = true;
+                tree.pending = None;
+                self.insert(tree);
+            }
+
+            true
+        } else if region.is_empty() {
+            false
+        } else {
+            self.add_region(region, Some(pattern_index));
+            true
+        }
+    }
+
+    /// Inserts a [`Tree`], removing those that intersect its region
+    fn insert(&mut self, tree: Tree) {
+        for range in tree.region.iter() {
+            let intersecting: Vec<usize> = self.intersecting(range).map(|(i, _)| i).collect();
+            for i in intersecting.into_iter().rev() {
+                self.0.remove(i);
+            }
+        }
+
+        let i = self
+            .0
+            .binary_search_by(|other| other.region.cmp(&tree.region))
+            .unwrap_err();
+        self.0.insert(i, tree);
+    }
+
     /// Removes the `n`th element from the list of [`Range`]s
     pub fn remove(&mut self, n: usize) {
         self.0.remove(n);
     }
 
     /// Edits all the [`Ranges`] through an [`InputEdit`]
+    ///
+    /// This cancels the background parses of [`Tree`]s that the edit
+    /// touches, since their results would be outdated. Other parses,
+    /// as well as first parses, which would otherwise never finish
+    /// while typing, keep going, and the edit is applied to their
+    /// results.
     pub fn edit(&mut self, edit: &InputEdit) {
         for tree in self.0.iter_mut() {
+            let is_touched = tree
+                .region
+                .intersects_with(edit.start_byte..edit.old_end_byte);
+
+            if is_touched && tree.ts_tree.is_some() {
+                tree.pending = None;
+            } else if let Some(pending) = tree.pending.as_mut() {
+                pending.edit(edit);
+            }
+
             if let Some(ts_tree) = tree.ts_tree.as_mut() {
                 ts_tree.edit(edit);
             }
@@ -0,0 +1,260 @@
+use std::{ops::Range, sync::LazyLock};
+
+use duat_core::{
+    cmd::{self, CmdResult},
+    context::{self, Handle},
+    data::Pass,
+    form,
+    mode::{MouseButton, MouseEvent, MouseEventKind},
+    text::{Builder, Tagger, Text, TextMut, TextState, TwoPoints, txt},
+    ui::{PushSpecs, Side, Widget},
+};
+use tree_sitter::Node;
+
+use crate::{TsHandle, parser};
+
+/// Adds the command used for toggling the [`TreeView`]
+pub(crate) fn add_tree_view_command() {
+    cmd::add("tree-view", |pa: &mut Pass| -> CmdResult {
+        let handle = context::current_buffer(pa);
+
+        let tree_view = handle
+            .get_related::<TreeView>(pa)
+            .next()
+            .map(|(tree_view, _)| tree_view);
+        if let Some(tree_view) = tree_view {
+            tree_view.close(pa)?;
+            return Ok(None);
+        }
+
+        if handle.get_ts_parser(pa).is_none() {
+            return Err(txt!("Tree-sitter is not enabled for this buffer"));
+        }
+
+        let specs = PushSpecs {
+            side: Side::Right,
+            width: Some(50.0),
+            ..PushSpecs::default()
+        };
+        handle.push_outer_widget(pa, TreeView::new(handle.clone()), specs);
+
+        Ok(None)
+    })
+    .doc(
+        txt!("Toggles a view of the syntax tree of the current buffer"),
+        None,
+    );
+}
+
+/// A [`Widget`] that shows the syntax tree of a [`Buffer`]
+///
+/// The trees of injected languages are shown below the main one.
+/// The node under the main caret of the `Buffer` is highlighted
+/// with the `selected.TreeView` form, and clicking on a node selects
+/// its range in the `Buffer`.
+///
+/// [`Buffer`]: duat_core::buffer::Buffer
+pub struct TreeView {
+    text: Text,
+    buffer: Handle,
+    node_ranges: Vec<Range<usize>>,
+    text_state: Option<TextState>,
+    selected: Option<usize>,
+}
+
+impl TreeView {
+    /// Returns a new `TreeView`, for the tree of a [`Buffer`]
+    ///
+    /// [`Buffer`]: duat_core::buffer::Buffer
+    pub fn new(buffer: Handle) -> Self {
+        Self {
+            text: Text::default(),
+            buffer,
+            node_ranges: Vec::new(),
+            text_state: None,
+            selected: None,
+        }
+    }
+
+    /// The line of the smallest node that contains a [`Range`]
+    ///
+    /// If many nodes have the same size, the last one is preferred,
+    /// since it is either a descendant or part of an injection.
+    fn line_of(&self, range: Range<usize>) -> Option<usize> {
+        self.node_ranges
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, node)| node.start <= range.start && range.end <= node.end)
+            .min_by_key(|(_, node)| node.len())
+            .map(|(line, _)| line)
+    }
+}
+
+impl Widget for TreeView {
+    fn update(pa: &mut Pass, handle: &Handle<Self>) {
+        let buffer = handle.read(pa).buffer.clone();
+        if buffer.is_closed(pa) {
+            _ = handle.close(pa);
+            return;
+        }
+
+        let last_state = handle.read(pa).text_state;
+
+        let Some((parser, buf)) = parser::sync_parse(pa, &buffer) else {
+            return;
+        };
+
+        let text_state = buf.text().text_state();
+        let caret_range = buf
+            .selections()
+            .get_main()
+            .map(|sel| sel.byte_range(buf.bytes()));
+
+        let rebuilt = (last_state != Some(text_state)).then(|| {
+            let mut builder = Text::builder();
+            let mut node_ranges = Vec::new();
+
+            for (lang, ts_tree) in parser.ts_trees() {
+                let root = ts_tree.root_node();
+                builder.push(txt!("[comment.TreeView]; {lang}\n"));
+                node_ranges.push(root.byte_range());
+                format_root(root, &mut builder, &mut node_ranges);
+            }
+
+            (builder.build(), node_ranges)
+        });
+
+        let tree_view = handle.write(pa);
+        let was_rebuilt = rebuilt.is_some();
+        if let Some((text, node_ranges)) = rebuilt {
+            tree_view.text = text;
+            tree_view.node_ranges = node_ranges;
+            tree_view.text_state = Some(text_state);
+        }
+
+        let selected = caret_range.and_then(|range| tree_view.line_of(range));
+        if selected != tree_view.selected || was_rebuilt {
+            let tagger = tree_view_tagger();
+            tree_view.text.remove_tags(tagger, ..);
+            tree_view.selected = selected;
+
+            if let Some(line) = selected {
+                let range = tree_view.text.bytes().line(line).byte_range();
+                let point = tree_view.text.bytes().point_at_byte(range.start);
+                let form = form::id_of!("selected.TreeView");
+                tree_view.text.insert_tag(tagger, range, form.to_tag(99));
+
+                handle.scroll_to_points(pa, TwoPoints::new_after_ghost(point));
+            }
+        }
+
+        handle.read(pa).buffer.declare_as_read();
+    }
+
+    fn on_mouse_event(pa: &mut Pass, handle: &Handle<Self>, event: MouseEvent)
+    where
+        Self: Sized,
+    {
+        let (MouseEventKind::Down(MouseButton::Left), Some(points)) = (event.kind, event.points)
+        else {
+            return;
+        };
+
+        let tree_view = handle.read(pa);
+        let line = points.points().real.line();
+        let Some(range) = tree_view.node_ranges.get(line).cloned() else {
+            return;
+        };
+
+        tree_view
+            .buffer
+            .clone()
+            .edit_main(pa, |mut c| c.move_to(range));
+    }
+
+    fn needs_update(&self, pa: &Pass) -> bool {
+        self.buffer.has_changed(pa)
+    }
+
+    fn text(&self) -> &Text {
+        &self.text
+    }
+
+    fn text_mut(&mut self) -> TextMut<'_> {
+        self.text.as_mut()
+    }
+}
+
+/// Formats a [`Node`] and its named descendants, one per line
+///
+/// The byte [`Range`] of each formatted node is pushed to
+/// `node_ranges`, in the same order as the lines.
+fn format_root(node: Node, builder: &mut Builder, node_ranges: &mut Vec<Range<usize>>) {
+    fn format_range(node: Node, builder: &mut Builder) {
+        let mut first = true;
+        for point in [node.start_position(), node.end_position()] {
+            builder.push(txt!(
+                "[punctuation.bracket.TreeView][[[coords.TreeView]{}\
+             	 [punctuation.delimiter.TreeView],[] [coords.TreeView]{}\
+             	 [punctuation.bracket.TreeView]]]",
+                point.row,
+                point.column
+            ));
+
+            if first {
+                first = false;
+                builder.push(txt!("[punctuation.delimiter],[] "));
+            }
+        }
+        builder.push("\n");
+    }
+
+    fn format_node(
+        node: Node,
+        depth: usize,
+        pars: usize,
+        builder: &mut Builder,
+        node_ranges: &mut Vec<Range<usize>>,
+        name: Option<&str>,
+    ) {
+        node_ranges.push(node.byte_range());
+        builder.push("  ".repeat(depth));
+
+        if let Some(name) = name {
+            builder.push(txt!("[node.field]{name}[punctuation.delimiter.TreeView]: "));
+        }
+
+        builder.push(txt!("[punctuation.bracket.TreeView]("));
+        builder.push(txt!("[node.name]{}", node.grammar_name()));
+
+        let mut cursor = node.walk();
+        let named_children = node.named_children(&mut cursor);
+        let len = named_children.len();
+
+        if len == 0 {
+            builder.push(txt!(
+                "[punctuation.bracket.TreeView]{}[] ",
+                ")".repeat(pars)
+            ));
+            format_range(node, builder);
+        } else {
+            builder.push(" ");
+            format_range(node, builder);
+
+            for (i, child) in named_children.enumerate() {
+                let name = node.field_name_for_named_child(i as u32);
+                let pars = if i == len - 1 { pars + 1 } else { 1 };
+                format_node(child, depth + 1, pars, builder, node_ranges, name);
+            }
+        }
+    }
+
+    format_node(node, 0, 1, builder, node_ranges, None);
+}
+
+/// The [`Tagger`] for the selected node
+fn tree_view_tagger() -> Tagger {
+    static TAGGER: LazyLock<Tagger> = Tagger::new_static();
+    *TAGGER
+}
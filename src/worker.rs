@@ -0,0 +1,159 @@
+use std::{
+    ops::ControlFlow,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use duat_core::form::FormId;
+use tree_sitter::{
+    InputEdit, Language, ParseOptions, ParseState, Parser as TsParser, Range as TsRange,
+    Tree as TsTree,
+};
+
+use crate::{LangParts, locals::SemanticForms, parser::semantic_forms_of, snapshot::Snapshot};
+
+static JOBS: LazyLock<mpsc::Sender<ParseJob>> = LazyLock::new(|| {
+    let (sender, receiver) = mpsc::channel::<ParseJob>();
+
+    thread::Builder::new()
+        .name("duat-treesitter".to_string())
+        .spawn(move || {
+            let mut parser = TsParser::new();
+            for job in receiver {
+                job.run(&mut parser);
+            }
+        })
+        .unwrap();
+
+    sender
+});
+
+/// A [`TsTree`] that is being parsed on the worker thread
+///
+/// Dropping this cancels the parse, and its result is thrown away.
+#[derive(Debug)]
+pub struct PendingParse {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    edits: Vec<InputEdit>,
+}
+
+impl PendingParse {
+    /// The id of the [`ParsedTree`] that will be sent back
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Records an [`InputEdit`] made after the parse was sent
+    ///
+    /// These have to be applied to the [`ParsedTree`] when it is
+    /// merged, since it was parsed from the bytes before them.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        self.edits.push(*edit);
+    }
+
+    /// The [`InputEdit`]s made after the parse was sent
+    pub fn edits(&self) -> &[InputEdit] {
+        &self.edits
+    }
+}
+
+impl Drop for PendingParse {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A [`TsTree`] that was parsed on the worker thread
+pub struct ParsedTree {
+    pub id: u64,
+    pub ts_tree: TsTree,
+    pub semantic: Option<SemanticForms>,
+}
+
+/// Sends a [`TsTree`] to be parsed on the worker thread
+///
+/// The `old_tree` should already have been edited to match the
+/// `snapshot`. If `semantic` is [`Some`], the [`SemanticForms`] of
+/// the new tree are computed as well. Once parsing is done,
+/// `on_parsed` is called from the worker thread, unless the returned
+/// [`PendingParse`] was dropped in the meantime.
+pub fn parse_in_background(
+    language: &'static Language,
+    ranges: Vec<TsRange>,
+    old_tree: Option<TsTree>,
+    snapshot: Snapshot,
+    semantic: Option<(LangParts<'static>, &'static [(FormId, u8)])>,
+    on_parsed: impl FnOnce(ParsedTree) + Send + 'static,
+) -> PendingParse {
+    static ID: AtomicU64 = AtomicU64::new(0);
+
+    let id = ID.fetch_add(1, Ordering::Relaxed);
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    _ = JOBS.send(ParseJob {
+        id,
+        language,
+        ranges,
+        old_tree,
+        snapshot,
+        semantic,
+        cancelled: cancelled.clone(),
+        on_parsed: Box::new(on_parsed),
+    });
+
+    PendingParse { id, cancelled, edits: Vec::new() }
+}
+
+struct ParseJob {
+    id: u64,
+    language: &'static Language,
+    ranges: Vec<TsRange>,
+    old_tree: Option<TsTree>,
+    snapshot: Snapshot,
+    semantic: Option<(LangParts<'static>, &'static [(FormId, u8)])>,
+    cancelled: Arc<AtomicBool>,
+    on_parsed: Box<dyn FnOnce(ParsedTree) + Send>,
+}
+
+impl ParseJob {
+    fn run(self, parser: &mut TsParser) {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        parser.reset();
+        if parser.set_language(self.language).is_err()
+            || parser.set_included_ranges(&self.ranges).is_err()
+        {
+            return;
+        }
+
+        let mut callback = |_: &ParseState| match self.cancelled.load(Ordering::Relaxed) {
+            true => ControlFlow::Break(()),
+            false => ControlFlow::Continue(()),
+        };
+
+        let ts_tree = parser.parse_with_options(
+            &mut |byte, _| self.snapshot.slice_from(byte),
+            self.old_tree.as_ref(),
+            Some(ParseOptions::new().progress_callback(&mut callback)),
+        );
+
+        let Some(ts_tree) = ts_tree else {
+            return;
+        };
+
+        let semantic = self.semantic.map(|(lang_parts, forms)| {
+            semantic_forms_of(lang_parts, forms, &ts_tree, &self.snapshot.to_bytes())
+        });
+
+        if !self.cancelled.load(Ordering::Relaxed) {
+            (self.on_parsed)(ParsedTree { id: self.id, ts_tree, semantic });
+        }
+    }
+}